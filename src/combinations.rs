@@ -0,0 +1,405 @@
+//! Generating combinations of features to build and run a binary crate with, and tasks for them.
+use crate::constraints::{Constraints, OnViolation};
+use crate::covering;
+use crate::group::{ChildInfo, Features, ParallelTasks, TaskSettings};
+use crate::indicators::BinaryCrateName;
+use crate::manifest::{self, FeatureTable};
+use core::borrow::Borrow;
+use core::num::NonZeroUsize;
+use core::slice;
+use std::collections::HashSet;
+
+/// Which combinations of toggled units [combinations] generates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    /// All combinations (up to [CombinationSettings::max_size] units).
+    #[default]
+    All,
+    /// A covering array: for any `strength` units, every combination of their on/off states is in
+    /// at least one generated combination. (Strength 2 is pairwise.) Far fewer combinations than
+    /// [Sampling::All], but it ignores [CombinationSettings::max_size].
+    ///
+    /// The result is the same for the same units, strength and seed.
+    Covering { strength: NonZeroUsize, seed: u64 },
+}
+
+/// How to limit the combinations generated by [combinations]. The default is the full powerset.
+#[derive(Clone, Debug, Default)]
+pub struct CombinationSettings {
+    /// Max. number of toggled units (features, or groups) in a combination. [None] means no limit.
+    /// Features in [CombinationSettings::always_on] don't count.
+    pub max_size: Option<usize>,
+    /// Groups of features that are always toggled together. Each group counts as one unit.
+    pub groups: Vec<Vec<String>>,
+    /// Features that are in every combination (and that are not toggled).
+    pub always_on: Vec<String>,
+    pub sampling: Sampling,
+    pub constraints: Constraints,
+    /// Whether to generate combinations that violate [CombinationSettings::constraints].
+    pub on_violation: OnViolation,
+    /// The sub-crate's features. If present, [CombinationSettings::constraints] apply to the
+    /// effective features of each combination (see [Constraints::violation]).
+    pub table: Option<FeatureTable>,
+    /// Whether the combinations are to be built without default features (which matters for their
+    /// effective features, as per [CombinationSettings::table]).
+    pub no_default_features: bool,
+}
+
+/// All combinations (subsets) of the given features, including the empty one. Ordered by the
+/// number of features, then by the order of `features` (as given).
+///
+/// There are 2^n of them, so this is practical only for a small number of features. Otherwise see
+/// [combinations].
+pub fn powerset(features: &[String]) -> Vec<Features<'_, str>> {
+    combinations(features, &FULL_POWERSET)
+}
+
+/// The same as [CombinationSettings::default], but usable for any lifetime.
+static FULL_POWERSET: CombinationSettings = CombinationSettings {
+    max_size: None,
+    groups: Vec::new(),
+    always_on: Vec::new(),
+    sampling: Sampling::All,
+    constraints: Constraints {
+        mutually_exclusive: Vec::new(),
+        requires: Vec::new(),
+        forbidden: Vec::new(),
+    },
+    on_violation: OnViolation::Skip,
+    table: None,
+    no_default_features: false,
+};
+
+/// Combinations of the given features, as per `settings`. Features listed in
+/// [CombinationSettings::always_on] come first in every combination. The rest of each combination
+/// consists of toggled units: each [CombinationSettings::groups] entry is one unit, and any other
+/// feature is a unit on its own.
+///
+/// Under [Sampling::All] ordered by the number of units, then by the order of `features` (as
+/// given). (A group is placed where its first feature is in `features`; any groups with no feature
+/// in `features` come last.) Under [Sampling::Covering] ordered as generated.
+///
+/// Combinations that violate [CombinationSettings::constraints] are left out, unless
+/// [CombinationSettings::on_violation] is [OnViolation::Keep]. (Under [Sampling::Covering] leaving
+/// them out may leave some tuples uncovered.)
+pub fn combinations<'a>(
+    features: &'a [String],
+    settings: &'a CombinationSettings,
+) -> Vec<Features<'a, str>> {
+    let always_on: Features<'a, str> = settings.always_on.iter().map(String::as_str).collect();
+    let is_always_on = |feature: &str| always_on.contains(&feature);
+
+    let mut units: Vec<Features<'a, str>> = Vec::with_capacity(features.len());
+    let mut groups_placed = vec![false; settings.groups.len()];
+    let mut place_group = |units: &mut Vec<Features<'a, str>>, index: usize| {
+        if !groups_placed[index] {
+            groups_placed[index] = true;
+            units.push(
+                settings.groups[index]
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|feature| !is_always_on(feature))
+                    .collect(),
+            );
+        }
+    };
+    for feature in features {
+        if is_always_on(feature) {
+            continue;
+        }
+        match settings
+            .groups
+            .iter()
+            .position(|group| group.contains(feature))
+        {
+            Some(index) => place_group(&mut units, index),
+            None => units.push(vec![feature.as_str()]),
+        }
+    }
+    for index in 0..settings.groups.len() {
+        place_group(&mut units, index);
+    }
+
+    let unit_combinations: Vec<Vec<Features<'a, str>>> = match settings.sampling {
+        Sampling::All => {
+            let max_size = settings
+                .max_size
+                .map_or(units.len(), |max_size| max_size.min(units.len()));
+            (0..=max_size)
+                .flat_map(|size| combinations_of_size(&units, size))
+                .collect()
+        }
+        Sampling::Covering { strength, seed } => {
+            covering::covering_array(units.len(), strength.get(), seed)
+                .into_iter()
+                .map(|row| {
+                    units
+                        .iter()
+                        .zip(row)
+                        .filter(|(_, on)| *on)
+                        .map(|(unit, _)| unit.clone())
+                        .collect()
+                })
+                .collect()
+        }
+    };
+    unit_combinations
+        .into_iter()
+        .map(|combination| {
+            always_on
+                .iter()
+                .copied()
+                .chain(combination.into_iter().flatten())
+                .collect()
+        })
+        .filter(|combination: &Features<'a, str>| {
+            settings.on_violation == OnViolation::Keep
+                || settings
+                    .constraints
+                    .violation_of_build(
+                        combination,
+                        settings.table.as_ref(),
+                        settings.no_default_features,
+                    )
+                    .is_none()
+        })
+        .collect()
+}
+
+/// Leave out any feature sets that build the same crate as an earlier one, because their features
+/// resolve to the same effective features through the feature graph of the manifest (see
+/// [manifest::resolve_features]), resolved with or without default features, as per
+/// `no_default_features`. For example, if feature `a` enables `b`, then `[a, b]` is the same as
+/// `[a]`. Otherwise the order is kept.
+///
+/// Under [Sampling::All] the earlier feature set is a smaller one, so the smallest feature set of
+/// each such duplicate is kept.
+pub fn dedupe<'a>(
+    feature_sets: Vec<Features<'a, str>>,
+    table: &FeatureTable,
+    no_default_features: bool,
+) -> Vec<Features<'a, str>> {
+    let mut seen = HashSet::with_capacity(feature_sets.len());
+    feature_sets
+        .into_iter()
+        .filter(|features| {
+            seen.insert(manifest::resolve_features(
+                table,
+                features,
+                no_default_features,
+            ))
+        })
+        .collect()
+}
+
+/// All combinations of exactly `size` items, each in the order of `items`.
+pub(crate) fn combinations_of_size<T: Clone>(items: &[T], size: usize) -> Vec<Vec<T>> {
+    let mut combinations = Vec::new();
+    if size > items.len() {
+        return combinations;
+    }
+    // Indices (into `items`) of the current combination, ascending.
+    let mut indices: Vec<usize> = (0..size).collect();
+    loop {
+        combinations.push(indices.iter().map(|&i| items[i].clone()).collect());
+        // Find the rightmost index that can still move right, move it, and reset the ones after.
+        let movable = (0..size)
+            .rev()
+            .find(|&position| indices[position] < items.len() - size + position);
+        match movable {
+            Some(position) => {
+                indices[position] += 1;
+                for next in position + 1..size {
+                    indices[next] = indices[next - 1] + 1;
+                }
+            }
+            None => return combinations,
+        }
+    }
+}
+
+/// Description of a task, used as its [ChildInfo].
+pub fn child_info<'a, S>(
+    sub_dir: &S,
+    binary_crate: &BinaryCrateName<'a, S>,
+    features: &Features<'a, S>,
+    settings: &TaskSettings,
+) -> ChildInfo
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    format!(
+        "{}/ binary crate {} with features [{}]{}{}{}{}",
+        sub_dir.borrow(),
+        binary_crate.borrow(),
+        features
+            .iter()
+            .map(|feature| feature.borrow())
+            .collect::<Vec<_>>()
+            .join(", "),
+        if settings.no_default_features {
+            ", no default features"
+        } else {
+            ""
+        },
+        if settings.all_features {
+            ", all features"
+        } else {
+            ""
+        },
+        settings
+            .profile
+            .as_ref()
+            .map_or_else(String::new, |profile| format!(", profile {profile}")),
+        if settings.args.is_empty() {
+            String::new()
+        } else {
+            format!(", args {:?}", settings.args)
+        }
+    )
+}
+
+/// One task per feature set, all of the same binary crate (under the same sub dir), and with the
+/// same settings. The meta of each task is its feature set.
+///
+/// Unless `settings` already disable default features (or enable all features), the first task is
+/// the "no defaults" baseline: no features, and [TaskSettings::no_default_features]. However, if
+/// the sub-crate's `table` is given and it has no [manifest::DEFAULT_FEATURE], there is no
+/// baseline: it would build the same as no features (with defaults).
+///
+/// To be run by [crate::run::parallel_single_tasks], or as a step of
+/// [crate::run::parallel_sequences_of_parallel_tasks].
+pub fn parallel_tasks<'a, S, #[allow(non_camel_case_types)] FEATURE_SETS>(
+    sub_dir: &'a S,
+    binary_crate: &'a BinaryCrateName<'a, S>,
+    feature_sets: FEATURE_SETS,
+    table: Option<&FeatureTable>,
+    settings: &TaskSettings,
+) -> ParallelTasks<'a, S, Features<'a, S>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+    FEATURE_SETS: IntoIterator<Item = Features<'a, S>>,
+{
+    let has_defaults = table.map_or(true, |table| table.contains_key(manifest::DEFAULT_FEATURE));
+    let baseline = if settings.no_default_features || settings.all_features || !has_defaults {
+        None
+    } else {
+        Some((
+            Features::new(),
+            TaskSettings {
+                no_default_features: true,
+                ..settings.clone()
+            },
+        ))
+    };
+    baseline
+        .into_iter()
+        .chain(
+            feature_sets
+                .into_iter()
+                .map(|features| (features, settings.clone())),
+        )
+        .map(|(features, settings)| {
+            let info = child_info(sub_dir, binary_crate, &features, &settings);
+            (
+                sub_dir,
+                binary_crate,
+                features.clone(),
+                settings,
+                info,
+                features,
+            )
+        })
+        .collect()
+}
+
+/// [parallel_tasks] for each of the `variants` of settings, in the order of `variants`: the matrix
+/// of feature sets and settings. See [settings_for_profiles] and [settings_for_args] for variants
+/// (or combine them, for a matrix of feature sets, profiles and arguments).
+pub fn parallel_tasks_for_settings<'a, S, #[allow(non_camel_case_types)] FEATURE_SETS>(
+    sub_dir: &'a S,
+    binary_crate: &'a BinaryCrateName<'a, S>,
+    feature_sets: FEATURE_SETS,
+    table: Option<&FeatureTable>,
+    variants: &[TaskSettings],
+) -> ParallelTasks<'a, S, Features<'a, S>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+    FEATURE_SETS: IntoIterator<Item = Features<'a, S>>,
+{
+    let feature_sets: Vec<Features<'a, S>> = feature_sets.into_iter().collect();
+    variants
+        .iter()
+        .flat_map(|settings| {
+            parallel_tasks(sub_dir, binary_crate, feature_sets.clone(), table, settings)
+        })
+        .collect()
+}
+
+/// Each of `variants` with each profile (as [TaskSettings::profile]), ordered by `profiles`, then
+/// by `variants`.
+pub fn settings_for_profiles(variants: &[TaskSettings], profiles: &[String]) -> Vec<TaskSettings> {
+    profiles
+        .iter()
+        .flat_map(|profile| {
+            variants.iter().map(move |settings| TaskSettings {
+                profile: Some(profile.clone()),
+                ..settings.clone()
+            })
+        })
+        .collect()
+}
+
+/// Each of `variants` with each set of command-line arguments (as [TaskSettings::args]), ordered
+/// by `arg_sets`, then by `variants`.
+pub fn settings_for_args(variants: &[TaskSettings], arg_sets: &[Vec<String>]) -> Vec<TaskSettings> {
+    arg_sets
+        .iter()
+        .flat_map(|args| {
+            variants.iter().map(move |settings| TaskSettings {
+                args: args.clone(),
+                ..settings.clone()
+            })
+        })
+        .collect()
+}
+
+/// [parallel_tasks_for_settings] for each profile: the matrix of feature sets and profiles.
+pub fn parallel_tasks_for_profiles<'a, S, #[allow(non_camel_case_types)] FEATURE_SETS>(
+    sub_dir: &'a S,
+    binary_crate: &'a BinaryCrateName<'a, S>,
+    feature_sets: FEATURE_SETS,
+    profiles: &[String],
+    table: Option<&FeatureTable>,
+    settings: &TaskSettings,
+) -> ParallelTasks<'a, S, Features<'a, S>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+    FEATURE_SETS: IntoIterator<Item = Features<'a, S>>,
+{
+    let variants = settings_for_profiles(slice::from_ref(settings), profiles);
+    parallel_tasks_for_settings(sub_dir, binary_crate, feature_sets, table, &variants)
+}
+
+/// [parallel_tasks_for_settings] for each set of command-line arguments: the matrix of feature
+/// sets and arguments.
+pub fn parallel_tasks_for_args<'a, S, #[allow(non_camel_case_types)] FEATURE_SETS>(
+    sub_dir: &'a S,
+    binary_crate: &'a BinaryCrateName<'a, S>,
+    feature_sets: FEATURE_SETS,
+    arg_sets: &[Vec<String>],
+    table: Option<&FeatureTable>,
+    settings: &TaskSettings,
+) -> ParallelTasks<'a, S, Features<'a, S>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+    FEATURE_SETS: IntoIterator<Item = Features<'a, S>>,
+{
+    let variants = settings_for_args(slice::from_ref(settings), arg_sets);
+    parallel_tasks_for_settings(sub_dir, binary_crate, feature_sets, table, &variants)
+}
@@ -0,0 +1,147 @@
+//! Constraints on combinations of features: which combinations are invalid (for example, because
+//! the crate forbids them with `compile_error!`).
+use crate::group::{Features, ParallelTasks};
+use crate::manifest::{self, FeatureTable};
+use crate::task::ExpectedBuildFailure;
+use core::borrow::Borrow;
+
+/// Constraints applied by [crate::combinations::combinations]. See
+/// [crate::combinations::CombinationSettings::on_violation].
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    /// Sets of features, of which at most one (from each set) may be enabled.
+    pub mutually_exclusive: Vec<Vec<String>>,
+    /// Implications: if the feature (the first part) is enabled, all the listed features (the
+    /// second part) must be enabled, too.
+    pub requires: Vec<(String, Vec<String>)>,
+    /// Forbidden combinations: any combination that contains all features of an entry.
+    pub forbidden: Vec<Vec<String>>,
+}
+
+/// Which constraint (of [Constraints]) a combination violates.
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    #[error("Mutually exclusive features are enabled together: {0:?}.")]
+    MutuallyExclusive(Vec<String>),
+    #[error("Feature {feature} requires features {missing:?}, which are not enabled.")]
+    Requires {
+        feature: String,
+        missing: Vec<String>,
+    },
+    #[error("Forbidden combination of features: {0:?}.")]
+    Forbidden(Vec<String>),
+}
+
+/// What to do with combinations that violate [Constraints].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnViolation {
+    /// Don't generate them.
+    #[default]
+    Skip,
+    /// Generate them, so that they can be run with an expectation that their build fails (see
+    /// [Constraints::expect_build_failures]).
+    Keep,
+}
+
+impl Constraints {
+    /// The first constraint (in order of [Constraints] fields, and then of their entries) that the
+    /// given features violate, if any.
+    ///
+    /// With the sub-crate's `table`, the constraints apply to the effective features (see
+    /// [manifest::resolve_features]): for example, if feature `a` enables `b`, then `[a]` violates
+    /// anything that `[a, b]` does. Otherwise they apply to the features as listed.
+    pub fn violation<S>(&self, features: &[S], table: Option<&FeatureTable>) -> Option<Violation>
+    where
+        S: AsRef<str>,
+    {
+        self.violation_of_build(features, table, false)
+    }
+
+    /// Like [Constraints::violation], but (with `table`) without default features, if
+    /// `no_default_features`.
+    pub(crate) fn violation_of_build<S>(
+        &self,
+        features: &[S],
+        table: Option<&FeatureTable>,
+        no_default_features: bool,
+    ) -> Option<Violation>
+    where
+        S: AsRef<str>,
+    {
+        match table {
+            Some(table) => {
+                let resolved: Vec<String> =
+                    manifest::resolve_features(table, features, no_default_features)
+                        .into_iter()
+                        .collect();
+                self.violation_as_listed(&resolved)
+            }
+            None => self.violation_as_listed(features),
+        }
+    }
+
+    fn violation_as_listed<S>(&self, features: &[S]) -> Option<Violation>
+    where
+        S: AsRef<str>,
+    {
+        let is_enabled = |feature: &String| {
+            features
+                .iter()
+                .any(|enabled| enabled.as_ref() == feature.as_str())
+        };
+        for set in &self.mutually_exclusive {
+            let enabled: Vec<String> = set.iter().filter(|f| is_enabled(f)).cloned().collect();
+            if enabled.len() > 1 {
+                return Some(Violation::MutuallyExclusive(enabled));
+            }
+        }
+        for (feature, required) in &self.requires {
+            if is_enabled(feature) {
+                let missing: Vec<String> = required
+                    .iter()
+                    .filter(|f| !is_enabled(f))
+                    .cloned()
+                    .collect();
+                if !missing.is_empty() {
+                    return Some(Violation::Requires {
+                        feature: feature.clone(),
+                        missing,
+                    });
+                }
+            }
+        }
+        self.forbidden
+            .iter()
+            .find(|combination| combination.iter().all(is_enabled))
+            .map(|combination| Violation::Forbidden(combination.clone()))
+    }
+
+    /// Whether the features satisfy all constraints. See [Constraints::violation].
+    pub fn allows(&self, features: &Features<'_, str>, table: Option<&FeatureTable>) -> bool {
+        self.violation(features, table).is_none()
+    }
+
+    /// Set [crate::group::TaskSettings::expected_build_failure] of each task whose features violate
+    /// the constraints (unless the task has its own expectation already). Other tasks are left as
+    /// they are. With `table` (of the tasks' sub-crate), see [Constraints::violation].
+    pub fn expect_build_failures<'a, S, M>(
+        &self,
+        tasks: &mut ParallelTasks<'a, S, M>,
+        table: Option<&FeatureTable>,
+    ) where
+        S: Borrow<str> + 'a + ?Sized,
+        &'a S: Borrow<str>,
+    {
+        for (_, _, features, settings, _, _) in tasks.iter_mut() {
+            if settings.expected_build_failure.is_none() {
+                let features: Vec<&str> = features.iter().map(|feature| feature.borrow()).collect();
+                if self
+                    .violation_of_build(&features, table, settings.no_default_features)
+                    .is_some()
+                {
+                    settings.expected_build_failure = Some(ExpectedBuildFailure::default());
+                }
+            }
+        }
+    }
+}
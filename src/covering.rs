@@ -0,0 +1,154 @@
+//! Covering arrays: a (small) set of rows of on/off states, such that for any `strength` columns,
+//! every combination of their on/off states appears in at least one row.
+//!
+//! We generate them greedily (similar to AETG): each new row is the best of several random
+//! candidates, where each candidate starts from a not yet covered tuple, and then its remaining
+//! columns are chosen one by one, to cover as many not yet covered tuples as possible. The random
+//! choices come from a seeded generator, so the result is deterministic for the same input.
+use crate::combinations;
+
+/// How many candidates to generate for each row.
+const CANDIDATES_PER_ROW: usize = 20;
+
+/// SplitMix64 - a small, fast, seedable pseudo-random generator. (Not for cryptography.)
+struct SplitMix64(u64);
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_bool(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+
+    /// A number in `0..bound` (with a negligible bias).
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.next_below(i + 1));
+        }
+    }
+}
+
+/// Tuples of columns, and which on/off states of each tuple are covered.
+struct Coverage {
+    tuples: Vec<Vec<usize>>,
+    /// Indexed by tuple index * 2^strength + states (bit `i` being the state of the tuple's `i`-th
+    /// column).
+    covered: Vec<bool>,
+    uncovered: usize,
+}
+impl Coverage {
+    fn new(columns: usize, strength: usize) -> Self {
+        let all_columns: Vec<usize> = (0..columns).collect();
+        let tuples = combinations::combinations_of_size(&all_columns, strength);
+        let uncovered = tuples.len() << strength;
+        Self {
+            tuples,
+            covered: vec![false; uncovered],
+            uncovered,
+        }
+    }
+
+    fn strength(&self) -> usize {
+        self.tuples.first().map_or(0, Vec::len)
+    }
+
+    fn index(&self, tuple: usize, row: &[bool]) -> usize {
+        let states = self.tuples[tuple]
+            .iter()
+            .enumerate()
+            .fold(0, |states, (bit, &column)| {
+                states | (usize::from(row[column]) << bit)
+            });
+        (tuple << self.strength()) + states
+    }
+
+    /// How many not yet covered tuple states the row would cover.
+    fn gain(&self, row: &[bool]) -> usize {
+        (0..self.tuples.len())
+            .filter(|&tuple| !self.covered[self.index(tuple, row)])
+            .count()
+    }
+
+    fn cover(&mut self, row: &[bool]) {
+        for tuple in 0..self.tuples.len() {
+            let index = self.index(tuple, row);
+            if !self.covered[index] {
+                self.covered[index] = true;
+                self.uncovered -= 1;
+            }
+        }
+    }
+
+    /// A random not yet covered tuple state: (tuple index, states).
+    fn random_uncovered(&self, random: &mut SplitMix64) -> (usize, usize) {
+        let nth = random.next_below(self.uncovered);
+        let index = (0..self.covered.len())
+            .filter(|&index| !self.covered[index])
+            .nth(nth)
+            .unwrap();
+        (
+            index >> self.strength(),
+            index & ((1 << self.strength()) - 1),
+        )
+    }
+}
+
+/// Rows (each with one on/off state per column) of a covering array of the given strength. If
+/// `strength` is more than `columns`, it's limited to `columns` (and then the result covers all
+/// 2^columns combinations).
+pub(crate) fn covering_array(columns: usize, strength: usize, seed: u64) -> Vec<Vec<bool>> {
+    let strength = strength.min(columns);
+    let mut coverage = Coverage::new(columns, strength);
+    let mut random = SplitMix64(seed);
+    let mut rows = Vec::new();
+    while coverage.uncovered > 0 {
+        let mut best: Option<(usize, Vec<bool>)> = None;
+        for _ in 0..CANDIDATES_PER_ROW {
+            let candidate = candidate_row(&coverage, columns, &mut random);
+            let gain = coverage.gain(&candidate);
+            if best
+                .as_ref()
+                .map_or(true, |(best_gain, _)| gain > *best_gain)
+            {
+                best = Some((gain, candidate));
+            }
+        }
+        let (_, row) = best.unwrap();
+        coverage.cover(&row);
+        rows.push(row);
+    }
+    rows
+}
+
+/// A row that covers a random not yet covered tuple state, with its other columns chosen greedily
+/// (in random order).
+fn candidate_row(coverage: &Coverage, columns: usize, random: &mut SplitMix64) -> Vec<bool> {
+    let (tuple, states) = coverage.random_uncovered(random);
+    let fixed = &coverage.tuples[tuple];
+    let mut row: Vec<bool> = (0..columns).map(|_| random.next_bool()).collect();
+    for (bit, &column) in fixed.iter().enumerate() {
+        row[column] = states & (1 << bit) != 0;
+    }
+
+    let mut others: Vec<usize> = (0..columns)
+        .filter(|column| !fixed.contains(column))
+        .collect();
+    random.shuffle(&mut others);
+    for column in others {
+        let current_gain = coverage.gain(&row);
+        row[column] = !row[column];
+        if coverage.gain(&row) <= current_gain {
+            row[column] = !row[column];
+        }
+    }
+    row
+}
@@ -1,22 +1,31 @@
 use crate::indicators::{BinaryCrateName, GroupEnd, SpawningMode};
-use crate::output::{DynErr, DynErrResult, OptOutput, ProcessOutput};
-use crate::task;
+use crate::kill::{self, ProcessGuard};
+use crate::output::{DynErr, DynErrResult, OptOutput, OutputAndOrError, ProcessOutput};
+use crate::reap;
+use crate::task::{
+    self, BuildCapture, BuildFailed, BuildOutput, BuildToken, ExecutableLease,
+    ExpectedBuildFailure, TargetDir,
+};
 use core::borrow::Borrow;
-use core::ops::{Deref, DerefMut};
+use core::mem;
+use core::num::NonZeroUsize;
 use core::time::Duration;
+use jobserver::Client;
 use phantom_newtype::Id;
-use std::collections::HashMap;
-use std::error::Error as StdError;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{self, Result as IoResult, Write};
+use std::path::PathBuf;
 use std::process::Child;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
-
-/// How long to sleep before checking again whether any child process(es) finished.
-const SLEEP_BETWEEN_CHECKING_CHILDREN: Duration = Duration::from_millis(10);
+use std::time::Instant;
 
 /// For disambiguation.
 pub type ChildProcess = Child;
 
+/// When a child times out, and the timeout (from when the child started) that gave that deadline.
+pub type Deadline = (Instant, Duration);
+
 /// Result of [Child]'s `id()` method, wrapped.
 //#[repr(transparent)]
 //pub sttrans ChildProcessId(pub u32);
@@ -26,7 +35,7 @@ pub type ChildInfo = String;
 
 /// Having a struct with (one) named field (for example, `its`), instead of a (one field) tuple
 /// struct, could make some code "nicer".
-/// ```rust
+/// ```ignore
 /// #[repr(transparent)]
 /// pub struct ChildInfoMeta<M> {
 ///     /* Not called `it`, so as not to confuse it with iterators.*/
@@ -34,8 +43,8 @@ pub type ChildInfo = String;
 /// }
 /// ```
 /// However, that would be useful mostly for either
-/// - accessing the (only) field, but that we can also dereference with asterisk (through [Deref]
-///   and [DerefMut]). Or
+/// - accessing the (only) field, but that we can also dereference with asterisk (through
+///   [core::ops::Deref] and [core::ops::DerefMut]). Or
 /// - accessing the anonymous/positional field(s) of the underlying tuple. But for that we have our
 ///   named accessor methods, so we don't need to access it through/by specifying the wrapped tuple
 ///   itself. So we don't need any of that much.
@@ -45,15 +54,63 @@ pub type ChildInfo = String;
 /// fields (and give them names as local variables).
 ///
 /// Why anonymous tuples (with nameless fields)? Brevity of positional constructor. And pattern matching.
-pub struct ChildInfoMeta<M>(ChildProcess, ChildInfo, M);
+///
+/// The fourth field is the [Deadline], if the child has a timeout. The last field is the task's
+/// [Phase].
+///
+/// The child is owned through a [ProcessGuard], so that it gets killed (together with its process
+/// group) if it's dropped while still running.
+pub struct ChildInfoMeta<M>(ProcessGuard, ChildInfo, M, Option<Deadline>, Phase);
 impl<M> ChildInfoMeta<M> {
     /// Useful if we don't want to publish the wrapped field.
-    /*pub fn new(process: ChildProcess, info: ChildInfo, meta: M) -> Self {
-        Self((process, info, meta))
-    }*/
+    pub fn new(process: ChildProcess, info: ChildInfo, meta: M) -> Self {
+        Self(
+            ProcessGuard::new(process),
+            info,
+            meta,
+            None,
+            Phase::Run(None),
+        )
+    }
+
+    /// A `cargo build` (as started by [task::build]) of a task. Once it succeeds, the built
+    /// executable is started (with the given timeout, if any) in its place.
+    pub(crate) fn building(
+        mut process: ChildProcess,
+        info: ChildInfo,
+        meta: M,
+        binary_crate: String,
+        settings: TaskSettings,
+        lease: ExecutableLease,
+        token: Option<BuildToken>,
+    ) -> Self {
+        let capture = BuildCapture::new(&mut process);
+        Self(
+            ProcessGuard::new(process),
+            info,
+            meta,
+            None,
+            Phase::Build {
+                binary_crate,
+                settings: Box::new(settings),
+                capture,
+                _lease: lease,
+                _token: token,
+            },
+        )
+    }
+
+    /// Set the child's timeout, counting from now.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.3 = Some((Instant::now() + timeout, timeout));
+        self
+    }
 
     pub fn child(&self) -> &ChildProcess {
-        &self.0
+        self.0.child()
+    }
+    pub fn child_mut(&mut self) -> &mut ChildProcess {
+        self.0.child_mut()
     }
     pub fn info(&self) -> &ChildInfo {
         &self.1
@@ -76,6 +133,42 @@ impl<M> ChildInfoMeta<M> {
     pub fn meta_mut(&mut self) -> &mut M {
         &mut self.2
     }
+
+    pub fn deadline(&self) -> Option<Deadline> {
+        self.3
+    }
+
+    /// Whether the child is a `cargo build` (rather than the built executable).
+    pub fn is_building(&self) -> bool {
+        matches!(self.4, Phase::Build { .. })
+    }
+}
+
+/// Which child process of its task a child is.
+pub(crate) enum Phase {
+    /// `cargo build`. The target directory is leased until the build fails, or until the built
+    /// executable has started.
+    Build {
+        binary_crate: String,
+        /// Settings of the task, with [TaskSettings::timeout] and [TaskSettings::profile] already
+        /// defaulted from [GroupSettings], and with [TaskSettings::working_dir] resolved. The
+        /// timeout is of the run (not of the build).
+        settings: Box<TaskSettings>,
+        capture: BuildCapture,
+        _lease: ExecutableLease,
+        /// Jobserver token (if any), released once the build has finished.
+        _token: Option<BuildToken>,
+    },
+    /// The built executable (or any other child), and the output of its build (if any).
+    Run(Option<BuildOutput>),
+}
+impl Phase {
+    fn into_build_output(self) -> Option<BuildOutput> {
+        match self {
+            Self::Build { .. } => None,
+            Self::Run(build_output) => build_output,
+        }
+    }
 }
 
 /// Only for [Copy], and only if NOT primitive.
@@ -93,22 +186,22 @@ where
 
 // @TODO From/Into
 
-/// Implementing [Deref] and [DerefMut] doesn't improve ergonomics for accessing the tuple's field
-/// by numeric index. For that we have to put the dereferencing asterisk and the struct instance
-/// into parenthesis, e.g.
-/// ```rust
-/// let wrap: ChildInfoMetaWrap = ...;
-/// meta = (*wrap).2;
-/// ```
-/// So we may just as well use
-/// ```rust
-/// let wrap: ChildInfoMetaWrap = ...;
-/// meta = wrap.0.2;
-/// ```
-/// - a little easier to type and read. However, we really want to use our named accessor methods.
-///
-/// But dereferencing with asterisk is ergonomic when we need to cast the struct back to the tuple
-/// type.
+// Implementing [Deref] and [DerefMut] doesn't improve ergonomics for accessing the tuple's field
+// by numeric index. For that we have to put the dereferencing asterisk and the struct instance
+// into parenthesis, e.g.
+// ```rust
+// let wrap: ChildInfoMetaWrap = ...;
+// meta = (*wrap).2;
+// ```
+// So we may just as well use
+// ```rust
+// let wrap: ChildInfoMetaWrap = ...;
+// meta = wrap.0.2;
+// ```
+// - a little easier to type and read. However, we really want to use our named accessor methods.
+//
+// But dereferencing with asterisk is ergonomic when we need to cast the struct back to the tuple
+// type.
 
 /// Group of active (running) Child processes.
 ///
@@ -120,19 +213,336 @@ pub type GroupOfChildren<M> = HashMap<ChildProcessId, ChildInfoMeta<M>>;
 pub type Features<'a, S> //where S: ?Sized,
     = Vec<&'a S /* feature */>;
 
-pub type ParallelTasks<'a, S, M> = Vec<(
+/// Settings of one task. Any [None] means to use the respective default from [GroupSettings].
+#[derive(Clone, Debug, Default)]
+pub struct TaskSettings {
+    /// How long the task may run. If it runs longer, it's killed and reported with [TimedOut].
+    pub timeout: Option<Duration>,
+    /// If [Some], the task's build is expected to fail (for example, because the features are
+    /// mutually exclusive, guarded by `compile_error!`). Then a matching build failure is reported
+    /// as success (with no executable run), and a successful build is reported with
+    /// [task::UnexpectedBuildSuccess].
+    pub expected_build_failure: Option<ExpectedBuildFailure>,
+    /// Build without the default features (`--no-default-features`).
+    pub no_default_features: bool,
+    /// Build with all features (`--all-features`), in addition to the task's [Features].
+    pub all_features: bool,
+    /// Cargo profile to build with: `dev`, `release`, or any custom profile of the sub-crate.
+    pub profile: Option<String>,
+    /// Which target directory to build in.
+    pub target_dir: TargetDir,
+    /// Environment variables for `cargo build` (for example `RUSTFLAGS`, or `CARGO_*`), in
+    /// addition to the inherited ones.
+    pub build_env: Vec<(String, String)>,
+    /// Environment variables for the built executable, in addition to the inherited ones (unless
+    /// [TaskSettings::clear_run_env]).
+    pub run_env: Vec<(String, String)>,
+    /// Whether the built executable does NOT inherit our environment (for hermetic runs). Then it
+    /// has [TaskSettings::run_env] only.
+    pub clear_run_env: bool,
+    /// Command-line arguments for the built executable.
+    pub args: Vec<String>,
+    /// Working directory of the built executable. A relative path is relative to the sub-crate's
+    /// directory. [None] means the sub-crate's directory itself.
+    pub working_dir: Option<PathBuf>,
+}
+
+/// One entry of [ParallelTasks].
+pub type ParallelTask<'a, S, M> = (
     &'a S, /* subdir */
     &'a BinaryCrateName<'a, S>,
     Features<'a, S>,
+    TaskSettings,
     ChildInfo,
     M,
-)>;
+);
+pub type ParallelTasks<'a, S, M> = Vec<ParallelTask<'a, S, M>>;
 
 pub(crate) type GroupExecution<M> = (GroupOfChildren<M>, SpawningMode);
+/// A queued task that can start: its index (in [TaskQueue]), manifest path, `--target-dir` (if
+/// any), and its binary name (see [task::binary_name]) with the lease - or the error that fails it.
+type Startable = (
+    usize,
+    PathBuf,
+    Option<PathBuf>,
+    DynErrResult<(String, ExecutableLease)>,
+);
 pub(crate) type GroupOfChildrenAndOptOutput<M> = (GroupOfChildren<M>, OptOutput<M>);
-pub(crate) type GroupExecutionAndStartErrors<M> = (GroupExecution<M>, Vec<DynErr>);
+pub(crate) type GroupExecutionAndOutputs<M> = (GroupExecution<M>, Vec<OutputAndOrError<M>>);
+
+/// Settings of a group of parallel tasks.
+#[derive(Clone, Debug)]
+pub struct GroupSettings {
+    /// Max. number of tasks (child processes) running at the same time. Any further tasks are
+    /// queued (in [TaskQueue]), and started only as earlier children are collected.
+    ///
+    /// A task that is being built counts, too: it has a slot reserved for its executable, so that
+    /// it can start as soon as its build succeeds.
+    pub max_in_flight: NonZeroUsize,
+    /// Max. number of tasks being built (by `cargo build`) at the same time. Any further tasks stay
+    /// queued, even if there is room for them under [GroupSettings::max_in_flight].
+    pub max_builds_in_flight: NonZeroUsize,
+    /// Whether each build needs a token from [task::jobserver] (the one inherited from an outer
+    /// `make`, if any) to start, and whether the build's `cargo` uses that jobserver. That
+    /// limits the builds of all groups (and threads) in this process, and of any outer `cargo`.
+    pub use_jobserver: bool,
+    /// Timeout for any tasks that don't have their own [TaskSettings::timeout]. [None] means no
+    /// timeout.
+    pub default_timeout: Option<Duration>,
+    /// Profile for any tasks that don't have their own [TaskSettings::profile].
+    pub default_profile: String,
+    /// Under [SpawningMode::StopAll]: How long to wait for the remaining children to finish after
+    /// asking them to terminate (`SIGTERM`), before we kill them (`SIGKILL`).
+    pub stop_grace_period: Duration,
+}
+impl Default for GroupSettings {
+    /// [GroupSettings::max_in_flight] and [GroupSettings::max_builds_in_flight] default to
+    /// [thread::available_parallelism] (or 1, if that is not known). The builds are limited by
+    /// the jobserver, too. [GroupSettings::default_profile] defaults to [DEFAULT_PROFILE], and
+    /// [GroupSettings::stop_grace_period] defaults to [DEFAULT_STOP_GRACE_PERIOD].
+    fn default() -> Self {
+        let parallelism =
+            thread::available_parallelism().unwrap_or_else(|_| NonZeroUsize::new(1).unwrap());
+        Self {
+            max_in_flight: parallelism,
+            max_builds_in_flight: parallelism,
+            use_jobserver: true,
+            default_timeout: None,
+            default_profile: DEFAULT_PROFILE.to_owned(),
+            stop_grace_period: DEFAULT_STOP_GRACE_PERIOD,
+        }
+    }
+}
+
+pub const DEFAULT_STOP_GRACE_PERIOD: Duration = Duration::from_secs(1);
+pub const DEFAULT_PROFILE: &str = "dev";
+
+/// How often to check a [FailureFlag] (if any) while waiting for children.
+const OTHERS_FAILURE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often to retry starting queued tasks that wait for their executable (see [ExecutableLease])
+/// or for a jobserver token, both of which can be released by other groups (or processes).
+const BLOCKED_QUEUE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A flag shared by sequences (of groups) that run in parallel, indicating which of them (by their
+/// index) have failed. See [TaskQueue::stop_on_others_failure].
+#[derive(Clone, Debug, Default)]
+pub struct FailureFlag(Arc<Mutex<HashSet<usize>>>);
+impl FailureFlag {
+    /// Record that the given sequence has failed.
+    pub fn set(&self, sequence: usize) {
+        self.failed().insert(sequence);
+    }
+    /// Whether any sequence other than the given one has failed.
+    pub fn is_set_by_other_than(&self, sequence: usize) -> bool {
+        self.failed().iter().any(|&failed| failed != sequence)
+    }
+    fn failed(&self) -> MutexGuard<'_, HashSet<usize>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Tasks of a group that have not been started yet, because of [GroupSettings::max_in_flight].
+///
+/// Once the group's [SpawningMode] is not [SpawningMode::ProcessAll] anymore, no more queued tasks
+/// are started. They stay in the queue - see [TaskQueue::into_skipped].
+pub struct TaskQueue<'a, S, M>
+where
+    S: ?Sized,
+    &'a S: Borrow<str>,
+{
+    parent_dir: &'a S,
+    pending: VecDeque<ParallelTask<'a, S, M>>,
+    settings: GroupSettings,
+    /// Present if [GroupSettings::use_jobserver] (and if the jobserver could be set up).
+    jobserver: Option<Client>,
+    /// The flag to watch, and the index of this group's own sequence (whose failure doesn't count).
+    others_failure: Option<(FailureFlag, usize)>,
+}
+impl<'a, S, M> TaskQueue<'a, S, M>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    pub fn new(
+        tasks: ParallelTasks<'a, S, M>,
+        parent_dir: &'a S,
+        settings: &GroupSettings,
+    ) -> Self {
+        Self {
+            parent_dir,
+            pending: tasks.into(),
+            settings: settings.clone(),
+            jobserver: if settings.use_jobserver {
+                task::jobserver().ok()
+            } else {
+                None
+            },
+            others_failure: None,
+        }
+    }
+
+    /// Once the given flag is set by a sequence (running in parallel) other than `own_sequence`,
+    /// make this group behave as if one of its own tasks has failed (as per its [GroupEnd]).
+    pub fn stop_on_others_failure(&mut self, flag: FailureFlag, own_sequence: usize) {
+        self.others_failure = Some((flag, own_sequence));
+    }
+
+    fn others_failed(&self) -> bool {
+        matches!(
+            &self.others_failure,
+            Some((flag, own_sequence)) if flag.is_set_by_other_than(*own_sequence)
+        )
+    }
+
+    pub fn settings(&self) -> &GroupSettings {
+        &self.settings
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Tasks that have not been started. After the group's life cycle has finished, these are the
+    /// tasks that were skipped (because of [SpawningMode::FinishActive] or [SpawningMode::StopAll]).
+    pub fn into_skipped(self) -> ParallelTasks<'a, S, M> {
+        self.pending.into()
+    }
+
+    /// Add a task at the end of the queue. It will be started by [life_cycle_step] (if the
+    /// [SpawningMode] allows).
+    pub fn push(&mut self, task: ParallelTask<'a, S, M>) {
+        self.pending.push_back(task);
+    }
+
+    /// Start queued task(s), while there are fewer than [GroupSettings::max_in_flight] children
+    /// (and fewer than [GroupSettings::max_builds_in_flight] builds, and while a jobserver token is
+    /// available), and while the [SpawningMode] is [SpawningMode::ProcessAll]. Each task starts
+    /// with its `cargo build`; its executable is started once the build succeeds (see
+    /// [collect_finished_child]). A failure to start a task updates the [SpawningMode] (as per the
+    /// given [GroupEnd]), the same as a failure of a finished task would.
+    ///
+    /// Return output and/or error of any tasks that failed to start.
+    pub(crate) fn start_more(
+        &mut self,
+        (children, spawning_mode): &mut GroupExecution<M>,
+        until: &GroupEnd,
+    ) -> Vec<OutputAndOrError<M>> {
+        let mut errors = Vec::with_capacity(0);
+        while *spawning_mode == SpawningMode::ProcessAll && self.has_room(children) {
+            let (index, manifest_path, target_dir, binary_name_and_lease) =
+                match self.next_startable() {
+                    Some(startable) => startable,
+                    None => break,
+                };
+            let token = match &self.jobserver {
+                // If the jobserver fails, we build without a token (rather than not at all).
+                Some(jobserver) => match BuildToken::try_acquire(jobserver) {
+                    Ok(Some(token)) => Some(token),
+                    Ok(None) => break,
+                    Err(_) => None,
+                },
+                None => None,
+            };
+            let (_, _, features, mut task_settings, child_info, meta) =
+                self.pending.remove(index).unwrap();
+            if task_settings.profile.is_none() {
+                task_settings.profile = Some(self.settings.default_profile.clone());
+            }
+            let sub_crate_dir = manifest_path.parent().unwrap().to_owned();
+            task_settings.working_dir = Some(match &task_settings.working_dir {
+                Some(working_dir) => sub_crate_dir.join(working_dir),
+                None => sub_crate_dir,
+            });
+            let child_and_lease = binary_name_and_lease.and_then(|(binary_name, lease)| {
+                task::build(
+                    &manifest_path,
+                    &binary_name,
+                    &features,
+                    &task_settings,
+                    target_dir.as_deref(),
+                    self.jobserver.as_ref(),
+                )
+                .map(|child| (child, binary_name, lease))
+            });
+            match child_and_lease {
+                Ok((child, binary_name, lease)) => {
+                    let child_id = child.id().into();
+                    task_settings.timeout = task_settings.timeout.or(self.settings.default_timeout);
+                    let child_info_meta = ChildInfoMeta::building(
+                        child,
+                        child_info,
+                        meta,
+                        binary_name,
+                        task_settings,
+                        lease,
+                        token,
+                    );
+                    children.insert(child_id, child_info_meta);
+                }
+                Err(err) => {
+                    let (output, error) = (Some((None, child_info, meta, None)), Some(err));
+                    *spawning_mode =
+                        mem::take(spawning_mode).after_output_and_or_error(&output, &error, until);
+                    errors.push((output, error));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Whether the group can start another task, as per [GroupSettings::max_in_flight] and
+    /// [GroupSettings::max_builds_in_flight].
+    fn has_room(&self, children: &GroupOfChildren<M>) -> bool {
+        let builds = children
+            .values()
+            .filter(|child_info_meta| child_info_meta.is_building())
+            .count();
+        children.len() < self.settings.max_in_flight.get()
+            && builds < self.settings.max_builds_in_flight.get()
+    }
+
+    /// Whether there are queued tasks that [TaskQueue::start_more] could not start, even though
+    /// the group has room for them. Then they wait for others - see [BLOCKED_QUEUE_CHECK_INTERVAL].
+    fn is_blocked(&self, (children, spawning_mode): &GroupExecution<M>) -> bool {
+        *spawning_mode == SpawningMode::ProcessAll && !self.is_empty() && self.has_room(children)
+    }
+
+    /// Index of the first queued task whose executable is not being built by another task (see
+    /// [ExecutableLease]).
+    fn next_startable(&self) -> Option<Startable> {
+        self.pending.iter().enumerate().find_map(
+            |(index, (sub_dir, binary_crate, features, task_settings, ..))| {
+                let manifest_path = task::manifest_path_for_subdir(self.parent_dir, *sub_dir);
+                let target_dir = task::target_dir(&manifest_path, features, task_settings);
+                let binary_name_and_lease = match task::binary_name(&manifest_path, *binary_crate) {
+                    Ok(binary_name) => {
+                        let effective_target_dir = task::effective_target_dir(
+                            &manifest_path,
+                            target_dir.as_deref(),
+                            task_settings,
+                        );
+                        let lease =
+                            ExecutableLease::try_new(effective_target_dir, binary_name.clone())?;
+                        Ok((binary_name, lease))
+                    }
+                    // The task fails to start, with this error.
+                    Err(err) => Err(err),
+                };
+                Some((index, manifest_path, target_dir, binary_name_and_lease))
+            },
+        )
+    }
+}
 
-/// Start a group of parallel child process(es) - tasks, all under the same `parent_dir`.
+/// Start a group of parallel child process(es) - tasks, all under the same `parent_dir`. Start no
+/// more than [GroupSettings::max_in_flight] of them. Any other tasks stay in the result
+/// [TaskQueue], and they are started by [life_cycle_step] (or [life_cycle_loop]).
 ///
 /// This does NOT have a [crate::indicators::SpawningMode] parameter - we behave as if under
 /// [crate::indicators::SpawningMode::ProcessAll].
@@ -143,34 +553,23 @@ pub(crate) type GroupExecutionAndStartErrors<M> = (GroupExecution<M>, Vec<DynErr
 /// the [crate::indicators::SpawningMode] part of the result tuple is either
 /// [crate::indicators::SpawningMode::FinishActive] or [crate::indicators::SpawningMode::StopAll],
 /// depending on the given `until` ([GroupEnd]).
+///
+/// The [Vec] part of the result contains output and/or error of any tasks that failed to start.
+/// Their [ChildOutput] part has no [ProcessOutput].
 pub fn start_parallel_tasks<'a, S, M>(
     tasks: ParallelTasks<'a, S, M>,
     parent_dir: &'a S,
-    until: &'a GroupEnd,
-) -> GroupExecutionAndStartErrors<M>
-//@TODO change the output type to be only a Vec<DynErr>.
+    until: &GroupEnd,
+    settings: &GroupSettings,
+) -> (GroupExecutionAndOutputs<M>, TaskQueue<'a, S, M>)
 where
     S: Borrow<str> + 'a + ?Sized,
     &'a S: Borrow<str>,
 {
-    let mut children = GroupOfChildren::new();
-    let mut spawning_mode = SpawningMode::default();
-    let mut errors = Vec::with_capacity(0);
-
-    for (sub_dir, binary_crate, features, child_info, meta) in tasks {
-        let child_or_err = task::spawn(parent_dir, sub_dir, binary_crate, &features);
-
-        match child_or_err {
-            Ok(child) => {
-                children.insert(child.id().into(), ChildInfoMeta(child, child_info, meta));
-            }
-            Err(err) => {
-                spawning_mode = until.mode_after_error_in_same_group();
-                errors.push(err);
-            }
-        };
-    }
-    ((children, spawning_mode), errors)
+    let mut queue = TaskQueue::new(tasks, parent_dir, settings);
+    let mut execution = (GroupOfChildren::new(), SpawningMode::default());
+    let errors = queue.start_more(&mut execution, until);
+    ((execution, errors), queue)
 }
 
 /// Iterate over the given children max. once. Take the first finished child (if any), and return
@@ -182,8 +581,8 @@ where
 pub(crate) fn try_finished_child<M>(
     children: &mut GroupOfChildren<M>,
 ) -> DynErrResult<Option<ChildProcessId>> {
-    for (child_id, ChildInfoMeta(child, _, _)) in children.iter_mut() {
-        let opt_status_or_err = child.try_wait();
+    for (child_id, child_info_meta) in children.iter_mut() {
+        let opt_status_or_err = child_info_meta.child_mut().try_wait();
 
         match opt_status_or_err {
             Ok(Some(_exit_status)) => {
@@ -196,7 +595,7 @@ pub(crate) fn try_finished_child<M>(
     Ok(None)
 }
 
-pub(crate) fn print_output(output: &ProcessOutput) -> IoResult<()> {
+pub fn print_output(output: &ProcessOutput) -> IoResult<()> {
     // If we have both non-empty stdout and stderr, print stdout first, and stderr second. That way
     // the developer is more likely to notice (and there is less vertical distance to scroll up).
     {
@@ -224,20 +623,41 @@ pub fn collect_finished_child<M>(
     let finished_result = try_finished_child(&mut children);
     match finished_result {
         Ok(Some(child_id)) => {
-            let ChildInfoMeta(child, child_info, meta) = children.remove(&child_id).unwrap();
-            let (child_output, err) = match child.wait_with_output() {
+            let ChildInfoMeta(child, child_info, meta, _, phase) =
+                children.remove(&child_id).unwrap();
+            let build_output = match phase {
+                Phase::Build {
+                    binary_crate,
+                    settings,
+                    capture,
+                    ..
+                } => {
+                    let output = finish_build(
+                        &mut children,
+                        child,
+                        &binary_crate,
+                        &settings,
+                        capture,
+                        child_info,
+                        meta,
+                    );
+                    return Some((children, output));
+                }
+                Phase::Run(build_output) => build_output,
+            };
+            let (child_output, err) = match child.into_child().wait_with_output() {
                 Ok(child_output) => (Some(child_output), None),
                 Err(err) => (
                     None,
                     Some({
-                        let err: Box<dyn StdError> = Box::new(err);
+                        let err: DynErr = Box::new(err);
                         err
                     }),
                 ),
             };
             Some((
                 children,
-                Some((Some((child_output, child_info, meta)), err)),
+                Some((Some((child_output, child_info, meta, build_output)), err)),
             ))
         }
         Ok(None) => {
@@ -251,25 +671,303 @@ pub fn collect_finished_child<M>(
     }
 }
 
-#[must_use]
-pub fn life_cycle_step<M>(
-    (mut _children, mut _spawning_mode): GroupExecution<M>,
-    _until: &GroupEnd,
-) -> DynErrResult<()> {
-    /*match collect_finished_child(children) {
-
-    }*/
-    thread::sleep(SLEEP_BETWEEN_CHECKING_CHILDREN);
-    // @TODO kill
-    panic!()
+/// Collect a finished `cargo build`. If it succeeded, start the built executable in its place (add
+/// it to `children`), and return [None]. Otherwise return output and/or error of the task (with a
+/// [BuildFailed] error if the build itself failed).
+///
+/// Under [TaskSettings::expected_build_failure] the executable is never started: return output of
+/// the task, with no error if the build failed as expected.
+fn finish_build<M>(
+    children: &mut GroupOfChildren<M>,
+    child: ProcessGuard,
+    binary_crate: &str,
+    settings: &TaskSettings,
+    capture: BuildCapture,
+    child_info: ChildInfo,
+    meta: M,
+) -> OptOutput<M> {
+    let build_output = match child
+        .into_child()
+        .wait()
+        .and_then(|status| capture.finish(status))
+    {
+        Ok(build_output) => build_output,
+        Err(err) => return Some((Some((None, child_info, meta, None)), Some(Box::new(err)))),
+    };
+    let status = build_output.0.status;
+    if let Some(expected) = &settings.expected_build_failure {
+        let err = expected.check(&build_output.0).err();
+        return Some((Some((None, child_info, meta, Some(build_output))), err));
+    }
+    if !status.success() {
+        let err: DynErr = Box::new(BuildFailed { status });
+        return Some((
+            Some((None, child_info, meta, Some(build_output))),
+            Some(err),
+        ));
+    }
+    let started = task::executable_path(&build_output.0, binary_crate)
+        .and_then(|executable| Ok(task::run(&executable, &child_info, settings)?));
+    match started {
+        Ok(process) => {
+            let child_id = process.id().into();
+            let mut child_info_meta = ChildInfoMeta(
+                ProcessGuard::new(process),
+                child_info,
+                meta,
+                None,
+                Phase::Run(Some(build_output)),
+            );
+            if let Some(timeout) = settings.timeout {
+                child_info_meta = child_info_meta.with_timeout(timeout);
+            }
+            children.insert(child_id, child_info_meta);
+            None
+        }
+        Err(err) => Some((
+            Some((None, child_info, meta, Some(build_output))),
+            Some(err),
+        )),
+    }
 }
 
-#[must_use]
-pub fn life_cycle_loop<M>(
-    (mut _children, mut _spawning_mode): GroupExecution<M>,
-    _until: &GroupEnd,
-) -> DynErrResult<()> {
+/// A task has run longer than its timeout (see [TaskSettings::timeout] and
+/// [GroupSettings::default_timeout]), so it was killed.
+///
+/// This is reported as the error part of [OutputAndOrError], while its [ProcessOutput] (if any)
+/// contains the partial `stdout` and `stderr`, captured before the child was killed. Hence this is
+/// distinct from a child that exited with a non-zero [std::process::ExitStatus] by itself.
+#[derive(thiserror::Error, Debug)]
+#[error("Timed out after {after:?}.")]
+pub struct TimedOut {
+    pub after: Duration,
+}
+
+/// The earliest [Deadline] of the given children, if any of them has a timeout.
+fn earliest_deadline<M>(children: &GroupOfChildren<M>) -> Option<Deadline> {
+    children
+        .values()
+        .filter_map(ChildInfoMeta::deadline)
+        .min_by_key(|(deadline, _)| *deadline)
+}
+
+/// If any child has run past its [Deadline], kill it and collect its (partial) output, with a
+/// [TimedOut] error. Otherwise return [None].
+pub(crate) fn collect_timed_out_child<M>(children: &mut GroupOfChildren<M>) -> OptOutput<M> {
+    let now = Instant::now();
+    let child_id = children
+        .iter()
+        .find(|(_, child_info_meta)| {
+            matches!(child_info_meta.deadline(), Some((deadline, _)) if deadline <= now)
+        })
+        .map(|(child_id, _)| *child_id)?;
+
+    let ChildInfoMeta(child, child_info, meta, deadline, phase) =
+        children.remove(&child_id).unwrap();
+    let build_output = phase.into_build_output();
+    let mut child = child.into_child();
+    let after = deadline.unwrap().1;
+    if let Err(err) = kill::kill(&mut child) {
+        return Some((
+            Some((None, child_info, meta, build_output)),
+            Some(Box::new(err)),
+        ));
+    }
+    let err: DynErr = Box::new(TimedOut { after });
+    match child.wait_with_output() {
+        Ok(child_output) => Some((
+            Some((Some(child_output), child_info, meta, build_output)),
+            Some(err),
+        )),
+        Err(_) => Some((Some((None, child_info, meta, build_output)), Some(err))),
+    }
+}
+
+/// How a child ended under [SpawningMode::StopAll].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Termination {
+    /// The child had finished on its own, before we asked it to terminate.
+    Exited,
+    /// The child finished after we asked it to terminate (`SIGTERM`), within
+    /// [GroupSettings::stop_grace_period].
+    OnTerm,
+    /// The child was still running after [GroupSettings::stop_grace_period], so we killed it
+    /// (`SIGKILL`).
+    OnKill,
+}
+
+/// A child was stopped under [SpawningMode::StopAll], because another task failed.
+///
+/// This is reported as the error part of [OutputAndOrError]. As per [SpawningMode::StopAll], the
+/// output of such a child is ignored, so its [ChildOutput] part has no [ProcessOutput].
+#[derive(thiserror::Error, Debug)]
+#[error("Stopped ({termination:?}), because another task failed.")]
+pub struct Stopped {
+    pub termination: Termination,
+}
+
+fn stopped_output<M>(
+    ChildInfoMeta(child, child_info, meta, _, phase): ChildInfoMeta<M>,
+    termination: Termination,
+) -> DynErrResult<OutputAndOrError<M>> {
+    let mut child = child.into_child();
+    // Reap the child (so it doesn't become a zombie). If it has been reaped already, this only
+    // returns its status.
+    child.wait()?;
+    Ok((
+        Some((None, child_info, meta, phase.into_build_output())),
+        Some(Box::new(Stopped { termination })),
+    ))
+}
+
+/// Stop any and all (remaining) children: Ask them to terminate (`SIGTERM`), wait for them up to
+/// `grace_period`, then kill (`SIGKILL`) any that are still running. Their output is discarded, as
+/// per [SpawningMode::StopAll].
+///
+/// The same applies to the rest of each child's process group, even if the child itself exits on
+/// `SIGTERM` (or if it had exited already): any processes left in the group are killed after
+/// `grace_period`, so that no helpers survive as orphans.
+///
+/// Return a [Stopped] entry for each child, recording its [Termination].
+pub(crate) fn stop_all<M>(
+    children: &mut GroupOfChildren<M>,
+    grace_period: Duration,
+) -> DynErrResult<Vec<OutputAndOrError<M>>> {
+    let mut stopped = Vec::with_capacity(children.len());
+    let mut terminated = GroupOfChildren::new();
+    // IDs of all children, which are also IDs of their process groups.
+    let mut groups = Vec::with_capacity(children.len());
+    for (child_id, mut child_info_meta) in children.drain() {
+        groups.push(child_info_meta.child().id());
+        if child_info_meta.child_mut().try_wait()?.is_some() {
+            kill::terminate_group(child_info_meta.child().id())?;
+            stopped.push(stopped_output(child_info_meta, Termination::Exited)?);
+        } else {
+            kill::terminate(child_info_meta.child_mut())?;
+            terminated.insert(child_id, child_info_meta);
+        }
+    }
+
+    let deadline = Instant::now() + grace_period;
     loop {
-        panic!();
+        while let Some(child_id) = try_finished_child(&mut terminated)? {
+            let child_info_meta = terminated.remove(&child_id).unwrap();
+            stopped.push(stopped_output(child_info_meta, Termination::OnTerm)?);
+        }
+        let now = Instant::now();
+        if terminated.is_empty() || now >= deadline {
+            break;
+        }
+        reap::wait_for_any_child(&terminated, Some(deadline - now))?;
+    }
+
+    for (_, mut child_info_meta) in terminated.drain() {
+        kill::kill(child_info_meta.child_mut())?;
+        stopped.push(stopped_output(child_info_meta, Termination::OnKill)?);
+    }
+
+    kill::wait_for_groups(&groups, deadline)?;
+    for group in groups {
+        kill::kill_group(group)?;
+    }
+    Ok(stopped)
+}
+
+/// One step of the group's life cycle: Start any queued tasks (if there is room for them, and if
+/// the [SpawningMode] allows). Collect a finished child, or kill and collect a timed out child (if
+/// any), and update the [SpawningMode] accordingly. If the mode becomes (or already is)
+/// [SpawningMode::StopAll], stop any remaining children (see [Stopped]).
+///
+/// If the `queue` watches a [FailureFlag] (see [TaskQueue::stop_on_others_failure]) which has
+/// been set, the [SpawningMode] changes as if a task of this group has failed.
+///
+/// Return [None] when there are no children left (and none can be started). Otherwise return
+/// [Some] with the (possibly updated) [GroupExecution], and with output and/or error of any
+/// child(ren) that have finished (or failed to start) in this step.
+pub fn life_cycle_step<'a, S, M>(
+    mut execution: GroupExecution<M>,
+    queue: &mut TaskQueue<'a, S, M>,
+    until: &GroupEnd,
+) -> DynErrResult<Option<GroupExecutionAndOutputs<M>>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    if queue.others_failed() && !execution.1.has_error() {
+        execution.1 = until.mode_after_error_in_same_group();
+    }
+    let mut outputs = queue.start_more(&mut execution, until);
+    let (mut children, spawning_mode) = execution;
+    let grace_period = queue.settings().stop_grace_period;
+    if spawning_mode == SpawningMode::StopAll {
+        outputs.extend(stop_all(&mut children, grace_period)?);
+    }
+
+    let (mut children, opt_output) = match collect_finished_child(children) {
+        Some((mut children, None)) => {
+            let opt_output = collect_timed_out_child(&mut children);
+            (children, opt_output)
+        }
+        Some(children_and_opt_output) => children_and_opt_output,
+        None => {
+            let execution = (GroupOfChildren::new(), spawning_mode);
+            if queue.is_blocked(&execution) {
+                thread::sleep(BLOCKED_QUEUE_CHECK_INTERVAL);
+            } else if outputs.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some((execution, outputs)));
+        }
+    };
+
+    match opt_output {
+        Some((output, error)) => {
+            let spawning_mode = spawning_mode.after_output_and_or_error(&output, &error, until);
+            outputs.push((output, error));
+            if spawning_mode == SpawningMode::StopAll {
+                outputs.extend(stop_all(&mut children, grace_period)?);
+            }
+            Ok(Some(((children, spawning_mode), outputs)))
+        }
+        None => {
+            let mut timeout = earliest_deadline(&children)
+                .map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()));
+            let mut cap_timeout = |interval: Duration| {
+                timeout = Some(timeout.map_or(interval, |timeout| timeout.min(interval)));
+            };
+            if queue.others_failure.is_some() && !spawning_mode.has_error() {
+                cap_timeout(OTHERS_FAILURE_CHECK_INTERVAL);
+            }
+            let execution = (children, spawning_mode);
+            if queue.is_blocked(&execution) {
+                cap_timeout(BLOCKED_QUEUE_CHECK_INTERVAL);
+            }
+            reap::wait_for_any_child(&execution.0, timeout)?;
+            Ok(Some((execution, outputs)))
+        }
+    }
+}
+
+/// Run the group until all its children finish (or are killed, under
+/// [GroupEnd::OnFailureStopAll]), starting any queued tasks as earlier children are collected.
+/// Return output and/or error of every collected child (and of any tasks that failed to start),
+/// in the order they finished.
+///
+/// Children stopped under [SpawningMode::StopAll] are included with a [Stopped] error (and without
+/// their [ProcessOutput]). Tasks that were not started stay in `queue`.
+pub fn life_cycle_loop<'a, S, M>(
+    mut execution: GroupExecution<M>,
+    queue: &mut TaskQueue<'a, S, M>,
+    until: &GroupEnd,
+) -> DynErrResult<Vec<OutputAndOrError<M>>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    let mut outputs = Vec::with_capacity(execution.0.len() + queue.len());
+    while let Some((next_execution, step_outputs)) = life_cycle_step(execution, queue, until)? {
+        outputs.extend(step_outputs);
+        execution = next_execution;
     }
+    Ok(outputs)
 }
@@ -1 +1,235 @@
+//! A general graph of tasks: each task may depend on other tasks, which must succeed before it
+//! starts. This covers groups of sequences of groups (and any other nesting of groups and
+//! sequences).
+use crate::group::{self, ChildInfo, GroupSettings, ParallelTask, TaskQueue};
+use crate::indicators::{GroupEnd, SpawningMode};
+use crate::manifest;
+use crate::output::{self, DynErrResult, OutputAndOrError};
+use core::borrow::Borrow;
+use std::collections::HashSet;
 
+/// A task of a [TaskGraph], and indices of any other tasks (in the same graph) that it depends on.
+pub type GraphTask<'a, S, M> = (ParallelTask<'a, S, M>, Vec<usize>);
+
+/// An error detected when planning a [TaskGraph] (before running anything).
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    #[error("Task #{task} depends on task #{dependency}, which doesn't exist.")]
+    UnknownDependency { task: usize, dependency: usize },
+    /// Indices of tasks that form a cycle, each one depending on the next one (and the last one
+    /// depending on the first one).
+    #[error("Tasks form a dependency cycle: {0:?}.")]
+    Cycle(Vec<usize>),
+}
+
+/// A task was not run, because a task it depends on (directly or indirectly) has failed.
+///
+/// This is reported as the error part of [OutputAndOrError], whose [crate::output::ChildOutput]
+/// part has no [crate::output::ProcessOutput].
+#[derive(thiserror::Error, Debug)]
+#[error("Skipped due to task #{task}: {info}.")]
+pub struct SkippedDueTo {
+    /// Index of the failed task.
+    pub task: usize,
+    /// [ChildInfo] of the failed task.
+    pub info: ChildInfo,
+}
+
+/// Tasks with their dependencies, validated: all dependencies exist, and there are no cycles.
+pub struct TaskGraph<'a, S, M>
+where
+    S: ?Sized,
+    &'a S: Borrow<str>,
+{
+    tasks: Vec<ParallelTask<'a, S, M>>,
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl<'a, S, M> TaskGraph<'a, S, M>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    pub fn new(graph_tasks: Vec<GraphTask<'a, S, M>>) -> Result<Self, PlanError> {
+        let (tasks, dependencies): (Vec<_>, Vec<_>) = graph_tasks.into_iter().unzip();
+        for (task, task_dependencies) in dependencies.iter().enumerate() {
+            if let Some(&dependency) = task_dependencies.iter().find(|&&dep| dep >= tasks.len()) {
+                return Err(PlanError::UnknownDependency { task, dependency });
+            }
+        }
+        if let Some(cycle) = find_cycle(&dependencies) {
+            return Err(PlanError::Cycle(cycle));
+        }
+        Ok(Self {
+            tasks,
+            dependencies,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+/// For each task, indices of tasks that depend on it directly.
+fn dependents(dependencies: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut dependents = vec![Vec::new(); dependencies.len()];
+    for (task, task_dependencies) in dependencies.iter().enumerate() {
+        for &dependency in task_dependencies {
+            dependents[dependency].push(task);
+        }
+    }
+    dependents
+}
+
+/// Kahn's algorithm. If not all tasks can be ordered, return one of the cycles.
+fn find_cycle(dependencies: &[Vec<usize>]) -> Option<Vec<usize>> {
+    let dependents = dependents(dependencies);
+    let mut unresolved: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut ready: Vec<usize> = (0..dependencies.len())
+        .filter(|&task| unresolved[task] == 0)
+        .collect();
+    let mut ordered = HashSet::with_capacity(dependencies.len());
+    while let Some(task) = ready.pop() {
+        ordered.insert(task);
+        for &dependent in &dependents[task] {
+            unresolved[dependent] -= 1;
+            if unresolved[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+    if ordered.len() == dependencies.len() {
+        return None;
+    }
+
+    // Any unordered task depends on another unordered task. Follow them until we come back.
+    let mut path = Vec::new();
+    let mut task = (0..dependencies.len())
+        .find(|task| !ordered.contains(task))
+        .unwrap();
+    while !path.contains(&task) {
+        path.push(task);
+        task = *dependencies[task]
+            .iter()
+            .find(|dependency| !ordered.contains(dependency))
+            .unwrap();
+    }
+    let start = path.iter().position(|&on_path| on_path == task).unwrap();
+    Some(path.split_off(start))
+}
+
+/// Run the graph: start every task whose dependencies have all succeeded, in parallel (up to
+/// [GroupSettings::max_in_flight] at the same time). A failure of a task doesn't stop any other
+/// tasks, but any tasks that depend on it (directly or indirectly) are skipped (see
+/// [SkippedDueTo]).
+///
+/// The tasks are checked by [manifest::validate_tasks] first.
+///
+/// Return output and/or error of each task, in the same order as the tasks were given to
+/// [TaskGraph::new].
+pub fn run_task_graph<'a, S, M>(
+    graph: TaskGraph<'a, S, M>,
+    parent_dir: &'a S,
+    settings: &GroupSettings,
+) -> DynErrResult<Vec<OutputAndOrError<M>>>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    let TaskGraph {
+        tasks,
+        dependencies,
+    } = graph;
+    manifest::validate_tasks(parent_dir, &tasks)?;
+    let dependents = dependents(&dependencies);
+    let mut unresolved: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+    let mut results: Vec<Option<OutputAndOrError<M>>> = tasks.iter().map(|_| None).collect();
+
+    // The tasks run with their index prepended to their meta, so we know which one has finished.
+    let mut waiting: Vec<Option<ParallelTask<'a, S, (usize, M)>>> = tasks
+        .into_iter()
+        .enumerate()
+        .map(
+            |(index, (sub_dir, binary_crate, features, task_settings, info, meta))| {
+                Some((
+                    sub_dir,
+                    binary_crate,
+                    features,
+                    task_settings,
+                    info,
+                    (index, meta),
+                ))
+            },
+        )
+        .collect();
+
+    let until = GroupEnd::ProcessAll;
+    let mut queue = TaskQueue::new(Vec::new(), parent_dir, settings);
+    for (index, task) in waiting.iter_mut().enumerate() {
+        if unresolved[index] == 0 {
+            queue.push(task.take().unwrap());
+        }
+    }
+
+    let mut execution = (Default::default(), SpawningMode::default());
+    while let Some((next_execution, outputs)) =
+        group::life_cycle_step(execution, &mut queue, &until)?
+    {
+        for (output, error) in outputs {
+            let failed = output::has_error(&output, &error);
+            let (process_output, info, (index, meta), build_output) = match output {
+                Some(child_output) => child_output,
+                // We don't know which task this error belongs to.
+                None => return Err(error.unwrap()),
+            };
+            if failed {
+                skip_dependents(index, &info, &dependents, &mut waiting, &mut results);
+            } else {
+                for &dependent in &dependents[index] {
+                    unresolved[dependent] -= 1;
+                    if unresolved[dependent] == 0 {
+                        if let Some(task) = waiting[dependent].take() {
+                            queue.push(task);
+                        }
+                    }
+                }
+            }
+            results[index] = Some((Some((process_output, info, meta, build_output)), error));
+        }
+        execution = next_execution;
+    }
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("Every task is either run, or skipped."))
+        .collect())
+}
+
+/// Mark any tasks that (directly or indirectly) depend on the failed task as skipped.
+fn skip_dependents<'a, S, M>(
+    failed: usize,
+    failed_info: &ChildInfo,
+    dependents: &[Vec<usize>],
+    waiting: &mut [Option<ParallelTask<'a, S, (usize, M)>>],
+    results: &mut [Option<OutputAndOrError<M>>],
+) where
+    S: ?Sized,
+    &'a S: Borrow<str>,
+{
+    let mut to_skip = dependents[failed].clone();
+    while let Some(task) = to_skip.pop() {
+        if let Some((_, _, _, _, info, (_, meta))) = waiting[task].take() {
+            results[task] = Some((
+                Some((None, info, meta, None)),
+                Some(Box::new(SkippedDueTo {
+                    task: failed,
+                    info: failed_info.clone(),
+                })),
+            ));
+            to_skip.extend(&dependents[task]);
+        }
+    }
+}
@@ -23,6 +23,7 @@ where
     B: 'b + ?Sized,
     &'b B: Borrow<str>,
 {
+    #[allow(clippy::should_implement_trait)]
     pub fn borrow(&self) -> &str {
         match self {
             Self::Main => "main",
@@ -64,9 +65,10 @@ pub enum SequenceEnd {
 }
 
 /// Mode of handling task life cycle.
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Default)]
 pub enum SpawningMode {
     /// Default (until there is any error, or until we finish all tasks).
+    #[default]
     ProcessAll,
     /// Finish active tasks, collect their output. Don't start any new ones.
     FinishActive,
@@ -80,11 +82,6 @@ impl SpawningMode {
         self != &Self::ProcessAll
     }
 }
-impl Default for SpawningMode {
-    fn default() -> Self {
-        Self::ProcessAll
-    }
-}
 impl SpawningMode {
     pub fn after_output_and_or_error<M>(
         self,
@@ -96,7 +93,7 @@ impl SpawningMode {
             debug_assert_eq!(self, group_until.mode_after_error_in_same_group());
             self
         } else {
-            if output::has_error(&output, &error) {
+            if output::has_error(output, error) {
                 group_until.mode_after_error_in_same_group()
             } else {
                 debug_assert_eq!(self, SpawningMode::ProcessAll);
@@ -0,0 +1,173 @@
+//! Asking child processes to terminate, and killing them - together with any processes they have
+//! started themselves.
+//!
+//! On Unix each task is started in its own process group (see [own_process_group]), and we signal
+//! the whole process group. Then any helpers started by a binary under test get terminated/killed
+//! together with it, rather than being left as orphans (holding ports, files...).
+use crate::group::ChildProcess;
+use core::time::Duration;
+use std::io::Result as IoResult;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+/// How often [wait_for_groups] checks whether the process groups are empty.
+const GROUP_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Make the child (to be spawned by the given [Command]) a leader of its own (new) process group.
+pub(crate) fn own_process_group(command: &mut Command) -> &mut Command {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0)
+    }
+    #[cfg(not(unix))]
+    {
+        command
+    }
+}
+
+/// Send the signal to the child's process group. If the child doesn't lead a process group (it was
+/// not started with [own_process_group]), send the signal to the child only.
+#[cfg(unix)]
+fn signal(child: &ChildProcess, signal: libc::c_int) -> IoResult<()> {
+    // The child has not been reaped yet (we own it, and we only reap it through `std`), so its
+    // process ID (and the ID of its process group, if it leads one) has not been reused.
+    let pid = child.id() as libc::pid_t;
+    // SAFETY: `killpg` has no memory safety requirements.
+    if unsafe { libc::killpg(pid, signal) } == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() != Some(libc::ESRCH) {
+        return Err(err);
+    }
+    // SAFETY: `kill` has no memory safety requirements.
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Send the signal to the process group led by a child with the given process ID, even if that
+/// child has exited (and has been reaped) already. Return `false` if the group has no processes
+/// left (or if there is no such group).
+///
+/// The ID is not reused (as a process ID or a group ID) as long as the group has any processes.
+#[cfg(unix)]
+fn signal_group(group: u32, signal: libc::c_int) -> IoResult<bool> {
+    // SAFETY: `killpg` has no memory safety requirements.
+    if unsafe { libc::killpg(group as libc::pid_t, signal) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::ESRCH) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+/// Ask any processes left in the process group (led by a child with the given ID, see
+/// [own_process_group]) to terminate (with `SIGTERM`).
+#[cfg(unix)]
+pub(crate) fn terminate_group(group: u32) -> IoResult<()> {
+    signal_group(group, libc::SIGTERM).map(|_| ())
+}
+#[cfg(not(unix))]
+pub(crate) fn terminate_group(_group: u32) -> IoResult<()> {
+    Ok(())
+}
+
+/// Kill any processes left in the process group (led by a child with the given ID, see
+/// [own_process_group]), with `SIGKILL`.
+#[cfg(unix)]
+pub(crate) fn kill_group(group: u32) -> IoResult<()> {
+    signal_group(group, libc::SIGKILL).map(|_| ())
+}
+#[cfg(not(unix))]
+pub(crate) fn kill_group(_group: u32) -> IoResult<()> {
+    Ok(())
+}
+
+/// Wait until the process groups (led by children with the given IDs) have no processes left, or
+/// until the deadline. (Processes left in a group after its leader has exited are not our
+/// children, so we can't wait for them other than by polling.)
+#[cfg(unix)]
+pub(crate) fn wait_for_groups(groups: &[u32], deadline: Instant) -> IoResult<()> {
+    loop {
+        let mut any_left = false;
+        for &group in groups {
+            any_left |= signal_group(group, 0)?;
+        }
+        let now = Instant::now();
+        if !any_left || now >= deadline {
+            return Ok(());
+        }
+        thread::sleep(GROUP_CHECK_INTERVAL.min(deadline - now));
+    }
+}
+#[cfg(not(unix))]
+pub(crate) fn wait_for_groups(_groups: &[u32], _deadline: Instant) -> IoResult<()> {
+    Ok(())
+}
+
+/// Ask the child (and its process group) to terminate (with `SIGTERM`). It may take its time to
+/// finish, or it may even ignore this.
+///
+/// Where there are no signals, this kills the child (the same as [kill]).
+#[cfg(unix)]
+pub(crate) fn terminate(child: &mut ChildProcess) -> IoResult<()> {
+    signal(child, libc::SIGTERM)
+}
+#[cfg(not(unix))]
+pub(crate) fn terminate(child: &mut ChildProcess) -> IoResult<()> {
+    kill(child)
+}
+
+/// Kill the child (and its process group), with `SIGKILL` on Unix.
+#[cfg(unix)]
+pub(crate) fn kill(child: &mut ChildProcess) -> IoResult<()> {
+    signal(child, libc::SIGKILL)
+}
+#[cfg(not(unix))]
+pub(crate) fn kill(child: &mut ChildProcess) -> IoResult<()> {
+    child.kill()
+}
+
+/// Owner of a [ChildProcess]. If dropped (for example, when a [crate::group::GroupOfChildren] is
+/// dropped because of a panic), it kills the child together with its process group, and it reaps
+/// the child. If the child has exited already, it kills any processes left in its process group.
+///
+/// [ProcessGuard::into_child] releases the child, so that it can be consumed (for example, by
+/// [ChildProcess::wait_with_output]).
+pub(crate) struct ProcessGuard(Option<ChildProcess>);
+impl ProcessGuard {
+    pub fn new(child: ChildProcess) -> Self {
+        Self(Some(child))
+    }
+
+    pub fn child(&self) -> &ChildProcess {
+        self.0.as_ref().unwrap()
+    }
+    pub fn child_mut(&mut self) -> &mut ChildProcess {
+        self.0.as_mut().unwrap()
+    }
+
+    pub fn into_child(mut self) -> ChildProcess {
+        self.0.take().unwrap()
+    }
+}
+impl Drop for ProcessGuard {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.0.take() {
+            if matches!(child.try_wait(), Ok(Some(_))) {
+                let _ = kill_group(child.id());
+            } else {
+                let _ = kill(&mut child);
+                let _ = child.wait();
+            }
+        }
+    }
+}
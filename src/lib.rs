@@ -9,12 +9,18 @@
 //! Any `B` generic parameter is for [BinaryCrateName]. That's separate from `S` because of
 //! lifetimes and borrowing.
 
+pub mod combinations;
+pub mod constraints;
+mod covering;
 pub mod group;
-mod group_of_sequences_of_groups;
-mod indicators;
-mod output;
-mod run;
-mod sequence_of_groups;
-mod task;
+pub mod group_of_sequences_of_groups;
+pub mod indicators;
+mod kill;
+pub mod manifest;
+pub mod output;
+mod reap;
+pub mod run;
+pub mod sequence_of_groups;
+pub mod task;
 #[cfg(test)]
 mod unit_tests;
@@ -0,0 +1,335 @@
+//! Reading the manifest (`Cargo.toml`) of a sub-crate.
+use crate::group::ParallelTask;
+use crate::output::{DynErr, DynErrResult};
+use crate::task;
+use core::borrow::Borrow;
+use core::fmt;
+use core::iter;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Features of a manifest: each feature's name, and what it enables (other features, `dep:`
+/// dependencies...). Ordered by feature names.
+///
+/// Besides features declared under `[features]`, it contains the implicit feature of each optional
+/// dependency that no feature refers to with `dep:` (as Cargo does). Such a feature enables its
+/// dependency (`dep:` and the dependency's name).
+pub type FeatureTable = BTreeMap<String, Vec<String>>;
+
+/// The feature that Cargo enables, unless told otherwise.
+pub const DEFAULT_FEATURE: &str = "default";
+
+/// The manifest is not a valid TOML, or its `[features]` table is not as Cargo requires.
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid manifest {manifest_path}: {reason}")]
+pub struct InvalidManifest {
+    pub manifest_path: PathBuf,
+    pub reason: String,
+}
+
+/// Requested features that are not features of their sub-crate (see [FeatureTable]).
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("Unknown features: {}.", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+pub struct UnknownFeatures(pub Vec<UnknownFeature>);
+
+/// One entry of [UnknownFeatures].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownFeature {
+    pub sub_dir: String,
+    pub feature: String,
+    /// Features of the sub-crate with a similar name, the closest first.
+    pub suggestions: Vec<String>,
+}
+impl fmt::Display for UnknownFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (of {}/)", self.feature, self.sub_dir)?;
+        if !self.suggestions.is_empty() {
+            write!(f, " - did you mean {}?", self.suggestions.join(" or "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Dependency tables that may contain optional dependencies (each at the top level of a manifest,
+/// or under a `[target.*]` table).
+const DEPENDENCY_TABLES: [&str; 2] = ["dependencies", "build-dependencies"];
+
+fn read_manifest(manifest_path: &Path) -> DynErrResult<toml::Value> {
+    toml::from_str(&fs::read_to_string(manifest_path)?).map_err(|err| {
+        let err: DynErr = Box::new(InvalidManifest {
+            manifest_path: manifest_path.to_owned(),
+            reason: err.to_string(),
+        });
+        err
+    })
+}
+
+/// Read features of the given manifest (see [FeatureTable]). If it has no `[features]` table, and
+/// no optional dependencies, the result is empty.
+pub fn read_features(manifest_path: &Path) -> DynErrResult<FeatureTable> {
+    features_of_manifest(manifest_path, &read_manifest(manifest_path)?)
+}
+
+/// [read_features] of an already read manifest.
+fn features_of_manifest(
+    manifest_path: &Path,
+    manifest: &toml::Value,
+) -> DynErrResult<FeatureTable> {
+    let invalid = |reason: String| InvalidManifest {
+        manifest_path: manifest_path.to_owned(),
+        reason,
+    };
+
+    let mut table = FeatureTable::new();
+    let empty = toml::value::Table::new();
+    let features = match manifest.get("features") {
+        Some(features) => features
+            .as_table()
+            .ok_or_else(|| invalid("[features] is not a table.".to_owned()))?,
+        None => &empty,
+    };
+    for (feature, enables) in features {
+        let enables = enables
+            .as_array()
+            .and_then(|enables| {
+                enables
+                    .iter()
+                    .map(|enabled| enabled.as_str().map(str::to_owned))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or_else(|| invalid(format!("Feature {feature} is not an array of strings.")))?;
+        table.insert(feature.clone(), enables);
+    }
+    for dependency in optional_dependencies(manifest) {
+        let dep = format!("{DEPENDENCY_PREFIX}{dependency}");
+        let is_referred = table.values().flatten().any(|enabled| *enabled == dep);
+        if !is_referred && !table.contains_key(&dependency) {
+            table.insert(dependency, vec![dep]);
+        }
+    }
+    Ok(table)
+}
+
+/// Read `name` under `[package]` of the given manifest. That's also the name of the package's
+/// default binary crate (see [crate::indicators::BinaryCrateName::Main]).
+pub fn read_package_name(manifest_path: &Path) -> DynErrResult<String> {
+    match read_manifest(manifest_path)?
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(toml::Value::as_str)
+    {
+        Some(name) => Ok(name.to_owned()),
+        None => Err(Box::new(InvalidManifest {
+            manifest_path: manifest_path.to_owned(),
+            reason: "It has no [package] name.".to_owned(),
+        })),
+    }
+}
+
+/// Names of optional dependencies of the manifest.
+fn optional_dependencies(manifest: &toml::Value) -> Vec<String> {
+    dependencies(manifest)
+        .filter(|(_, dependency)| {
+            dependency.get("optional").and_then(toml::Value::as_bool) == Some(true)
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Dependencies of the manifest (from all [DEPENDENCY_TABLES]): name (as used in features) and
+/// specification.
+fn dependencies(manifest: &toml::Value) -> impl Iterator<Item = (&String, &toml::Value)> {
+    let targets = manifest
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values());
+    iter::once(manifest)
+        .chain(targets)
+        .flat_map(|parent| {
+            DEPENDENCY_TABLES
+                .iter()
+                .filter_map(move |table| parent.get(table).and_then(toml::Value::as_table))
+        })
+        .flatten()
+}
+
+/// Read `[features]` of the sub-crate's manifest. See [read_features].
+pub fn read_features_for_subdir<S>(parent_dir: &S, sub_dir: &S) -> DynErrResult<FeatureTable>
+where
+    S: Borrow<str> + ?Sized,
+{
+    read_features(&task::manifest_path_for_subdir(parent_dir, sub_dir))
+}
+
+/// Names of the features (other than [DEFAULT_FEATURE]), ordered. Those are the features to
+/// generate combinations of. They include implicit features of optional dependencies, but not of
+/// those hidden by `dep:` (see [FeatureTable]).
+pub fn feature_names(table: &FeatureTable) -> Vec<String> {
+    table
+        .keys()
+        .filter(|feature| *feature != DEFAULT_FEATURE)
+        .cloned()
+        .collect()
+}
+
+/// Prefix of an entry (of a feature) that enables an optional dependency, without enabling its
+/// implicit feature.
+const DEPENDENCY_PREFIX: &str = "dep:";
+
+/// Whether the dependency is optional (as far as the table can tell: some feature enables it with
+/// `dep:`, including its implicit feature, if any).
+fn is_optional_dependency(table: &FeatureTable, dependency: &str) -> bool {
+    table
+        .values()
+        .flatten()
+        .any(|enabled| enabled.strip_prefix(DEPENDENCY_PREFIX) == Some(dependency))
+}
+
+/// The effective features of a build with the given features (and with [DEFAULT_FEATURE], unless
+/// `no_default_features`): those features, and everything that they enable, transitively:
+/// - features of the table,
+/// - optional dependencies, as `dep:` entries (whether enabled with `dep:`, or with a `dep/feature`
+///   entry), and
+/// - features of dependencies, as `dependency/feature` entries. A weak `dependency?/feature` entry
+///   doesn't enable the dependency; its feature is included only if the dependency is enabled
+///   anyway (or if it's not optional).
+///
+/// Unknown features are not included. Two feature sets with the same effective features build the
+/// same crate.
+pub fn resolve_features<S>(
+    table: &FeatureTable,
+    features: &[S],
+    no_default_features: bool,
+) -> BTreeSet<String>
+where
+    S: AsRef<str>,
+{
+    let mut resolved = BTreeSet::new();
+    // (dependency, its feature) of weak entries.
+    let mut weak = Vec::new();
+    let mut pending: Vec<&str> = features.iter().map(AsRef::as_ref).collect();
+    if !no_default_features {
+        pending.push(DEFAULT_FEATURE);
+    }
+    while let Some(entry) = pending.pop() {
+        if entry.starts_with(DEPENDENCY_PREFIX) {
+            resolved.insert(entry.to_owned());
+        } else if let Some((dependency, feature)) = entry.split_once('/') {
+            if let Some(dependency) = dependency.strip_suffix('?') {
+                weak.push((dependency, feature));
+                continue;
+            }
+            resolved.insert(entry.to_owned());
+            // Like Cargo: enable the dependency's implicit feature, if it has one.
+            if table.contains_key(dependency) {
+                pending.push(dependency);
+            } else if is_optional_dependency(table, dependency) {
+                resolved.insert(format!("{DEPENDENCY_PREFIX}{dependency}"));
+            }
+        } else if let Some(enables) = table.get(entry) {
+            if resolved.insert(entry.to_owned()) {
+                pending.extend(enables.iter().map(String::as_str));
+            }
+        }
+    }
+    for (dependency, feature) in weak {
+        let is_enabled = !is_optional_dependency(table, dependency)
+            || resolved.contains(&format!("{DEPENDENCY_PREFIX}{dependency}"));
+        if is_enabled {
+            resolved.insert(format!("{dependency}/{feature}"));
+        }
+    }
+    resolved
+}
+
+/// Max. edit distance of a suggested feature name from an unknown one: a third of its length (but
+/// at least 1).
+fn max_suggestion_distance(feature: &str) -> usize {
+    (feature.chars().count() / 3).max(1)
+}
+
+/// Features of `table` with a name similar to `feature`, the closest first.
+pub fn suggestions(table: &FeatureTable, feature: &str) -> Vec<String> {
+    let max_distance = max_suggestion_distance(feature);
+    let mut similar: Vec<(usize, &String)> = table
+        .keys()
+        .map(|known| (strsim::levenshtein(feature, known), known))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    similar.sort();
+    similar
+        .into_iter()
+        .map(|(_, known)| known.clone())
+        .collect()
+}
+
+/// Check that all features of all tasks are features of their sub-crates (as per
+/// [read_features_for_subdir]), or `dependency/feature` (or weak `dependency?/feature`) entries of
+/// their dependencies (optional or not). Otherwise return [UnknownFeatures] (listing each unknown
+/// feature of a sub-crate once, in order of the tasks).
+///
+/// The runners (in [crate::run], [crate::sequence_of_groups] and
+/// [crate::group_of_sequences_of_groups]) call this before they build anything, so that a
+/// misspelled feature fails fast, rather than after any number of successful builds.
+///
+/// A sub-crate without a manifest is not checked (its tasks fail to start, as usual).
+pub fn validate_tasks<'a, 't, S, M, TASKS>(parent_dir: &S, tasks: TASKS) -> DynErrResult<()>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+    'a: 't,
+    M: 't,
+    TASKS: IntoIterator<Item = &'t ParallelTask<'a, S, M>>,
+{
+    // Features, and names of dependencies, of each sub-crate (with a manifest).
+    let mut tables: HashMap<&str, Option<(FeatureTable, HashSet<String>)>> = HashMap::new();
+    let mut unknown = Vec::<UnknownFeature>::new();
+    for (sub_dir, _, features, _, _, _) in tasks {
+        let sub_dir = (*sub_dir).borrow();
+        if !tables.contains_key(sub_dir) {
+            let manifest_path = task::manifest_path_for_subdir(parent_dir.borrow(), sub_dir);
+            let table = if manifest_path.is_file() {
+                let manifest = read_manifest(&manifest_path)?;
+                let dependencies = dependencies(&manifest)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                Some((
+                    features_of_manifest(&manifest_path, &manifest)?,
+                    dependencies,
+                ))
+            } else {
+                None
+            };
+            tables.insert(sub_dir, table);
+        }
+        let (table, dependencies) = match &tables[sub_dir] {
+            Some(table_and_dependencies) => table_and_dependencies,
+            None => continue,
+        };
+        for feature in features.iter().map(|feature| feature.borrow()) {
+            let is_reported = unknown
+                .iter()
+                .any(|reported| reported.sub_dir == sub_dir && reported.feature == feature);
+            let is_known = table.contains_key(feature)
+                || feature.split_once('/').map_or(false, |(dependency, _)| {
+                    // A weak `dependency?/feature` entry, too.
+                    let dependency = dependency.strip_suffix('?').unwrap_or(dependency);
+                    dependencies.contains(dependency)
+                });
+            if !is_known && !is_reported {
+                unknown.push(UnknownFeature {
+                    sub_dir: sub_dir.to_owned(),
+                    feature: feature.to_owned(),
+                    suggestions: suggestions(table, feature),
+                });
+            }
+        }
+    }
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(UnknownFeatures(unknown)))
+    }
+}
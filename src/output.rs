@@ -1,15 +1,22 @@
 use crate::group::ChildInfo;
+use crate::task::BuildOutput;
 use std::error::Error;
 use std::process::Output;
 
-pub type DynErr = Box<dyn Error>;
+/// [Send] and [Sync], so that results can be passed between threads (for example, from parallel
+/// sequences).
+pub type DynErr = Box<dyn Error + Send + Sync>;
 pub type DynErrResult<T> = Result<T, DynErr>;
 
 /// For disambiguation.
 pub type ProcessOutput = Output;
 
-/// [Output] part mey not be present, if [std::process::Child::wait_with_output] failed.
-pub type ChildOutput<M> = (Option<Output>, ChildInfo, M);
+/// [Output] part mey not be present, if [std::process::Child::wait_with_output] failed, or if the
+/// child failed to start.
+///
+/// The last part is the output of the task's build, if the task got built (even if the build
+/// failed).
+pub type ChildOutput<M> = (Option<Output>, ChildInfo, M, Option<BuildOutput>);
 pub type ChildOutputOption<M> = Option<ChildOutput<M>>;
 pub type DynErrOption = Option<DynErr>;
 
@@ -22,7 +29,7 @@ pub type OutputAndOrError<M> = (ChildOutputOption<M>, DynErrOption);
 pub fn has_error<M>(output_option: &ChildOutputOption<M>, error_option: &DynErrOption) -> bool {
     error_option.is_some()
         || {
-            matches!(output_option, Some((Some(out), _, _)) if !out.status.success() || !out.stderr.is_empty())
+            matches!(output_option, Some((Some(out), _, _, _)) if !out.status.success() || !out.stderr.is_empty())
         }
 }
 
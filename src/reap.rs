@@ -0,0 +1,116 @@
+//! Waiting for any child process of a [GroupOfChildren] to finish, without busy polling.
+//!
+//! On Linux this uses [pidfd_open(2)](https://man7.org/linux/man-pages/man2/pidfd_open.2.html)
+//! and [poll(2)](https://man7.org/linux/man-pages/man2/poll.2.html). A pidfd becomes readable once
+//! its process has terminated. This doesn't reap the process - [std::process::Child::try_wait]
+//! still does that, so the [std::process::Child] stays the owner of its process.
+//!
+//! Elsewhere (or on Linux < 5.3, which doesn't have `pidfd_open`) we fall back to sleeping for
+//! [SLEEP_BETWEEN_CHECKING_CHILDREN].
+use crate::group::GroupOfChildren;
+use core::time::Duration;
+use std::io::Result as IoResult;
+use std::thread;
+
+/// How long to sleep before checking again whether any child process(es) finished. Used only where
+/// we can't wait for the children themselves.
+pub(crate) const SLEEP_BETWEEN_CHECKING_CHILDREN: Duration = Duration::from_millis(10);
+
+/// Block until any of the given children (likely) has finished, or until `timeout` (if any) has
+/// passed. Return immediately if `children` is empty.
+///
+/// This may return early (spuriously). Hence the caller needs to check the children (for example,
+/// with [crate::group::collect_finished_child]) regardless.
+pub(crate) fn wait_for_any_child<M>(
+    children: &GroupOfChildren<M>,
+    timeout: Option<Duration>,
+) -> IoResult<()> {
+    if children.is_empty() {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if linux::wait_for_any_child(children, timeout)? {
+            return Ok(());
+        }
+    }
+    thread::sleep(match timeout {
+        Some(timeout) => timeout.min(SLEEP_BETWEEN_CHECKING_CHILDREN),
+        None => SLEEP_BETWEEN_CHECKING_CHILDREN,
+    });
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::group::GroupOfChildren;
+    use core::time::Duration;
+    use std::io::{Error as IoError, Result as IoResult};
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    /// Return `Ok(false)` if pidfd is not supported by the kernel, so that the caller falls back
+    /// to sleeping.
+    pub(super) fn wait_for_any_child<M>(
+        children: &GroupOfChildren<M>,
+        timeout: Option<Duration>,
+    ) -> IoResult<bool> {
+        let mut pidfds = Vec::with_capacity(children.len());
+        for child_id in children.keys() {
+            match pidfd_open(*child_id.get()) {
+                Ok(Some(pidfd)) => pidfds.push(pidfd),
+                // The process is gone already, so there is something to collect.
+                Ok(None) => return Ok(true),
+                Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut poll_fds: Vec<_> = pidfds
+            .iter()
+            .map(|pidfd| libc::pollfd {
+                fd: pidfd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout_ms = match timeout {
+            // Round up, so that we don't wake up (repeatedly) just before the timeout.
+            Some(timeout) => ((timeout.as_nanos() + 999_999) / 1_000_000)
+                .min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        // SAFETY: `poll_fds` is a valid array of `pollfd` of the given length, and the pidfds stay
+        // open (owned by `pidfds`) for the duration of the call.
+        let result = unsafe {
+            libc::poll(
+                poll_fds.as_mut_ptr(),
+                poll_fds.len() as libc::nfds_t,
+                timeout_ms,
+            )
+        };
+        if result < 0 {
+            let err = IoError::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Return [Ok] of [None] if the process doesn't exist anymore. If the kernel doesn't support
+    /// `pidfd_open`, return [Err] with [libc::ENOSYS].
+    fn pidfd_open(pid: u32) -> IoResult<Option<OwnedFd>> {
+        // SAFETY: `pidfd_open` has no memory safety requirements. The result is either -1, or a
+        // new file descriptor that we own.
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+        if fd < 0 {
+            let err = IoError::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ESRCH) => Ok(None),
+                _ => Err(err),
+            };
+        }
+        // SAFETY: `fd` is a freshly opened file descriptor, owned by nobody else.
+        Ok(Some(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) }))
+    }
+}
@@ -1,43 +1,173 @@
-#![allow(unused)]
-use crate::indicators::{GroupEnd, SequenceEnd};
+use crate::group::{self, FailureFlag, GroupSettings, ParallelTasks, TaskQueue};
+use crate::indicators::{BinaryCrateName, GroupEnd, SequenceEnd, SpawningMode};
+use crate::manifest;
+use crate::output::{self, DynErrResult, OutputAndOrError};
 use core::borrow::Borrow;
+use std::thread;
 
 /// Run a group of parallel binary crate invocations. Each item (a tuple) of the group consists of
-/// two fields:
-/// - subdirectory, and
-/// - crate feature name(s), if any.
+/// (see [ParallelTasks]):
+/// - subdirectory,
+/// - binary crate name,
+/// - crate feature name(s), if any,
+/// - task settings, and info and meta (to identify the task in the result).
 ///
-/// All entries are run in parallel. It's an error if two or more entries have the same subdirectory
-/// name.
-pub fn parallel_single_tasks<'s, S, FEATURES, TASKS>(
-    parent_dir: &S,
-    tasks: TASKS,
+/// All entries are run in parallel (up to [GroupSettings::max_in_flight] at the same time). Two or
+/// more entries may build the same binary crate: their builds take turns (unless they build under
+/// different [crate::task::TargetDir]s), but their built executables still run in parallel.
+///
+/// The tasks are checked by [manifest::validate_tasks] first.
+pub fn parallel_single_tasks<'s, S, M>(
+    parent_dir: &'s S,
+    tasks: ParallelTasks<'s, S, M>,
+    group_until: GroupEnd,
+    settings: &GroupSettings,
+) -> DynErrResult<Vec<OutputAndOrError<M>>>
+where
+    S: Borrow<str> + 's + ?Sized,
+    &'s S: Borrow<str>,
+{
+    manifest::validate_tasks(parent_dir, &tasks)?;
+    let ((execution, mut outputs), mut queue) =
+        group::start_parallel_tasks(tasks, parent_dir, &group_until, settings);
+    outputs.extend(group::life_cycle_loop(execution, &mut queue, &group_until)?);
+    Ok(outputs)
+}
+
+/// Result of one sequence of [parallel_sequences_of_parallel_tasks]:
+/// - output and/or error of each step (group) that was started, and
+/// - tasks that were skipped: the rest of any group that was stopped (or finished early), and all
+///   tasks of any subsequent groups.
+pub type SequenceOutputs<'s, S, M> = (Vec<Vec<OutputAndOrError<M>>>, Vec<ParallelTasks<'s, S, M>>);
+
+/// Run one sequence (in the current thread), the `index`-th one. Set `failure` (for this index) as
+/// soon as any of its tasks fails. Then skip any subsequent steps, unless `group_until` is
+/// [GroupEnd::ProcessAll]. If `sequence_end` is [SequenceEnd::StopOnOthersFailure], stop once
+/// `failure` is set by another sequence.
+fn run_sequence<'s, S, M, #[allow(non_camel_case_types)] SEQUENCE_TASKS>(
+    parent_dir: &'s S,
+    index: usize,
     group_until: GroupEnd,
-) where
+    sequence_end: SequenceEnd,
+    sequence_tasks: SEQUENCE_TASKS,
+    settings: &GroupSettings,
+    failure: &FailureFlag,
+) -> DynErrResult<SequenceOutputs<'s, S, M>>
+where
     S: Borrow<str> + 's + ?Sized,
-    FEATURES: IntoIterator<Item = S>,
-    TASKS: IntoIterator<Item = (&'s S /*binary crate name*/, FEATURES)>,
+    &'s S: Borrow<str>,
+    SEQUENCE_TASKS: IntoIterator<Item = ParallelTasks<'s, S, M>>,
 {
+    let mut steps = Vec::new();
+    let mut skipped = Vec::with_capacity(0);
+    let mut failed = false;
+
+    for tasks in sequence_tasks {
+        let stop = failed
+            || (matches!(sequence_end, SequenceEnd::StopOnOthersFailure)
+                && failure.is_set_by_other_than(index));
+        if stop {
+            skipped.push(tasks);
+            continue;
+        }
+        let mut queue = TaskQueue::new(tasks, parent_dir, settings);
+        if let SequenceEnd::StopOnOthersFailure = sequence_end {
+            queue.stop_on_others_failure(failure.clone(), index);
+        }
+        let mut execution = (Default::default(), SpawningMode::default());
+        let mut outputs = Vec::new();
+        while let Some((next_execution, step_outputs)) =
+            group::life_cycle_step(execution, &mut queue, &group_until)?
+        {
+            if step_outputs
+                .iter()
+                .any(|(output, error)| output::has_error(output, error))
+            {
+                if group_until.mode_after_error_in_same_group().has_error() {
+                    failed = true;
+                }
+                failure.set(index);
+            }
+            outputs.extend(step_outputs);
+            execution = next_execution;
+        }
+        steps.push(outputs);
+        if !queue.is_empty() {
+            skipped.push(queue.into_skipped());
+        }
+    }
+    Ok((steps, skipped))
 }
 
 /// Run multiple sequences, where each sequence step runs a group of task(s) in parallel.
 ///
-/// Their output may be reordered, to have any non-empty `stderr` at the end.
+/// The sequences themselves run in parallel, each in its own thread. (Each group still starts no
+/// more than [GroupSettings::max_in_flight] children at the same time.) Once any task fails:
+/// - its own sequence follows its [GroupEnd], and it doesn't start any subsequent steps (unless its
+///   [GroupEnd] is [GroupEnd::ProcessAll]), and
+/// - any other sequence with [SequenceEnd::StopOnOthersFailure] stops, too: its active group
+///   behaves as if one of its own tasks has failed (so it follows its own [GroupEnd]), and it
+///   doesn't start any subsequent steps.
+///
+/// All tasks (of all sequences) are checked by [manifest::validate_tasks] first.
+///
+/// Return one [SequenceOutputs] per sequence, in the same order as `sequences`.
 pub fn parallel_sequences_of_parallel_tasks<
     's,
     S,
-    #[allow(non_camel_case_types)] FEATURE_SET,
-    #[allow(non_camel_case_types)] PARALLEL_TASKS,
+    M,
     #[allow(non_camel_case_types)] SEQUENCE_TASKS,
     SEQUENCES,
 >(
-    parent_dir: &S,
+    parent_dir: &'s S,
     sequences: SEQUENCES,
-) where
-    S: Borrow<str> + 's + ?Sized,
-    FEATURE_SET: IntoIterator<Item = &'s S /* feature*/>,
-    PARALLEL_TASKS: IntoIterator<Item = (&'s S /* binary crate name*/, FEATURE_SET)>,
-    SEQUENCE_TASKS: IntoIterator<Item = PARALLEL_TASKS>,
+    settings: &GroupSettings,
+) -> DynErrResult<Vec<SequenceOutputs<'s, S, M>>>
+where
+    S: Borrow<str> + Sync + 's + ?Sized,
+    &'s S: Borrow<str>,
+    BinaryCrateName<'s, S>: Sync,
+    M: Send,
+    SEQUENCE_TASKS: IntoIterator<Item = ParallelTasks<'s, S, M>> + Send,
     SEQUENCES: IntoIterator<Item = (GroupEnd, SequenceEnd, SEQUENCE_TASKS)>,
 {
+    let sequences: Vec<_> = sequences
+        .into_iter()
+        .map(|(group_until, sequence_end, sequence_tasks)| {
+            let sequence_tasks: Vec<ParallelTasks<'s, S, M>> = sequence_tasks.into_iter().collect();
+            (group_until, sequence_end, sequence_tasks)
+        })
+        .collect();
+    manifest::validate_tasks(
+        parent_dir,
+        sequences
+            .iter()
+            .flat_map(|(_, _, sequence_tasks)| sequence_tasks.iter().flatten()),
+    )?;
+
+    let failure = FailureFlag::default();
+    thread::scope(|scope| {
+        let handles: Vec<_> = sequences
+            .into_iter()
+            .enumerate()
+            .map(|(index, (group_until, sequence_end, sequence_tasks))| {
+                let failure = &failure;
+                scope.spawn(move || {
+                    run_sequence(
+                        parent_dir,
+                        index,
+                        group_until,
+                        sequence_end,
+                        sequence_tasks,
+                        settings,
+                        failure,
+                    )
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("A sequence thread panicked."))
+            .collect()
+    })
 }
@@ -1,24 +1,112 @@
-use crate::indicators::GroupEnd;
+use crate::combinations;
+use crate::group::{self, Features, GroupSettings, ParallelTasks, TaskSettings};
+use crate::indicators::{BinaryCrateName, GroupEnd, SpawningMode};
+use crate::manifest;
+use crate::output::{DynErrResult, OutputAndOrError};
 use core::borrow::Borrow;
 
+/// Result of one feature set of [sequence_single_tasks].
+pub enum StepResult<'s, S>
+where
+    S: ?Sized,
+{
+    /// The task was run (or it failed to start).
+    Run(Features<'s, S>, OutputAndOrError<()>),
+    /// The task was not run, because an earlier task has failed (as per the [GroupEnd]).
+    Skipped(Features<'s, S>),
+}
+
+impl<'s, S> StepResult<'s, S>
+where
+    S: ?Sized,
+{
+    pub fn features(&self) -> &Features<'s, S> {
+        match self {
+            Self::Run(features, _) | Self::Skipped(features) => features,
+        }
+    }
+
+    /// Whether this is a task that has written to `stderr`, or that has an error (which is reported
+    /// to `stderr`).
+    pub fn has_stderr(&self) -> bool {
+        match self {
+            Self::Run(_, (output, error)) => {
+                error.is_some()
+                    || matches!(output, Some((Some(out), _, _, _)) if !out.stderr.is_empty())
+            }
+            Self::Skipped(_) => false,
+        }
+    }
+}
+
 /// Run a sequence of the same binary crate (under the same sub dir) invocation(s), but each
-/// invocation with possibly different combinations of crate features.
+/// invocation with possibly different combinations of crate features. Each feature set is built and
+/// run with the same `task_settings`.
 ///
 /// The tasks are run in sequence, but their output may be reordered, to have any non-empty `stderr`
 /// at the end.
+///
+/// There is one [StepResult] per feature set. After a task fails, any subsequent tasks are skipped,
+/// unless `group_until` is [GroupEnd::ProcessAll]. (In a sequence there is no other active task, so
+/// [GroupEnd::OnFailureStopAll] and [GroupEnd::OnFailureFinishActive] behave the same.)
+///
+/// All feature sets are validated first (see [manifest::validate_tasks]).
 pub fn sequence_single_tasks<
     's,
     S,
     #[allow(non_camel_case_types)] FEATURE_SET,
     #[allow(non_camel_case_types)] FEATURE_SETS,
 >(
-    parent_dir: &S,
-    sub_dir: &S,
+    parent_dir: &'s S,
+    sub_dir: &'s S,
+    binary_crate: &'s BinaryCrateName<'s, S>,
     feature_sets: FEATURE_SETS,
+    task_settings: &TaskSettings,
     group_until: GroupEnd,
-) where
+    settings: &GroupSettings,
+) -> DynErrResult<Vec<StepResult<'s, S>>>
+where
     S: Borrow<str> + 's + ?Sized,
+    &'s S: Borrow<str>,
     FEATURE_SET: IntoIterator<Item = &'s S>,
     FEATURE_SETS: IntoIterator<Item = FEATURE_SET>,
 {
+    let tasks: ParallelTasks<'s, S, ()> = feature_sets
+        .into_iter()
+        .map(|feature_set| {
+            let features: Features<'s, S> = feature_set.into_iter().collect();
+            let child_info =
+                combinations::child_info(sub_dir, binary_crate, &features, task_settings);
+            (
+                sub_dir,
+                binary_crate,
+                features,
+                task_settings.clone(),
+                child_info,
+                (),
+            )
+        })
+        .collect();
+    manifest::validate_tasks(parent_dir, &tasks)?;
+
+    let mut results = Vec::new();
+    let mut spawning_mode = SpawningMode::default();
+
+    for task in tasks {
+        let features = task.2.clone();
+        if spawning_mode.has_error() {
+            results.push(StepResult::Skipped(features));
+            continue;
+        }
+        let ((execution, start_errors), mut queue) =
+            group::start_parallel_tasks(vec![task], parent_dir, &group_until, settings);
+        let outputs = group::life_cycle_loop(execution, &mut queue, &group_until)?;
+        for (output, error) in start_errors.into_iter().chain(outputs) {
+            spawning_mode = spawning_mode.after_output_and_or_error(&output, &error, &group_until);
+            results.push(StepResult::Run(features.clone(), (output, error)));
+        }
+    }
+    // Stable sort, so the order is otherwise kept.
+    results.sort_by_key(StepResult::has_stderr);
+    Ok(results)
 }
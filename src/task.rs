@@ -1,53 +1,483 @@
-use crate::group::{ChildProcess, Features};
+//! Building a binary crate (with `cargo build`), and running the built executable. Both are child
+//! processes of their task: first the build, then (if the build succeeded) the run.
+use crate::group::{ChildProcess, Features, TaskSettings, DEFAULT_PROFILE};
 use crate::indicators::BinaryCrateName;
-use crate::output::DynErrResult;
+use crate::kill;
+use crate::manifest;
+use crate::output::{DynErrResult, ProcessOutput};
 use core::borrow::Borrow;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use test_binary::TestBinary;
+use core::num::NonZeroUsize;
+use core::time::Duration;
+use jobserver::{Acquired, Client};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, Read, Result as IoResult};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
-fn manifest_path_for_subdir<S>(parent_dir: &S, sub_dir: &S) -> PathBuf
+/// Captured output of `cargo build` (its `stdout` being JSON messages, its `stderr` being rendered
+/// diagnostics), and how long the build took.
+pub type BuildOutput = (ProcessOutput, Duration);
+
+/// `cargo build` of a task has failed. Its output (with the diagnostics) is in the task's
+/// [BuildOutput].
+#[derive(thiserror::Error, Debug)]
+#[error("Build failed: {status}.")]
+pub struct BuildFailed {
+    pub status: ExitStatus,
+}
+
+/// `cargo build` has succeeded, but it has not reported an executable for the binary crate.
+#[derive(thiserror::Error, Debug)]
+#[error("Cargo reported no executable for binary crate {binary_crate}.")]
+pub struct NoExecutable {
+    pub binary_crate: String,
+}
+
+/// `cargo build` of a task has succeeded, even though the task expected it to fail (see
+/// [ExpectedBuildFailure]).
+#[derive(thiserror::Error, Debug)]
+#[error("Build succeeded, but it was expected to fail.")]
+pub struct UnexpectedBuildSuccess;
+
+/// `cargo build` of a task has failed as expected, but its diagnostics don't contain
+/// [ExpectedBuildFailure::message].
+#[derive(thiserror::Error, Debug)]
+#[error("Build failed ({status}), but its diagnostics don't contain {expected_message:?}.")]
+pub struct BuildFailedDifferently {
+    pub status: ExitStatus,
+    pub expected_message: String,
+}
+
+/// An expectation that a task's build fails. See
+/// [crate::group::TaskSettings::expected_build_failure].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExpectedBuildFailure {
+    /// If [Some], the build's compiler diagnostics (its `stderr`) must contain this (as a
+    /// substring).
+    pub message: Option<String>,
+}
+impl ExpectedBuildFailure {
+    /// [Ok] if the build has failed as expected. Otherwise [UnexpectedBuildSuccess] or
+    /// [BuildFailedDifferently].
+    pub fn check(&self, build_output: &ProcessOutput) -> DynErrResult<()> {
+        if build_output.status.success() {
+            return Err(Box::new(UnexpectedBuildSuccess));
+        }
+        match &self.message {
+            Some(message) if !String::from_utf8_lossy(&build_output.stderr).contains(message) => {
+                Err(Box::new(BuildFailedDifferently {
+                    status: build_output.status,
+                    expected_message: message.clone(),
+                }))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Path to `Cargo.toml` of the sub-crate.
+pub fn manifest_path_for_subdir<S>(parent_dir: &S, sub_dir: &S) -> PathBuf
 where
     S: Borrow<str> + ?Sized,
 {
     PathBuf::from_iter([parent_dir.borrow(), sub_dir.borrow(), "Cargo.toml"])
 }
 
-pub fn spawn<'a, S>(
-    parent_dir: &S,
-    sub_dir: &S,
-    binary_crate: &BinaryCrateName<'a, S>,
+/// Where `cargo build` of a task puts its artifacts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TargetDir {
+    /// Cargo's default: `CARGO_TARGET_DIR` (if set, shared by all sub-crates), or the sub-crate's
+    /// `target/`. Builds of the same binary crate take turns (see [ExecutableLease]).
+    #[default]
+    Shared,
+    /// The sub-crate's `target/`, even if `CARGO_TARGET_DIR` is set. Builds of different
+    /// sub-crates don't wait for each other's lock.
+    PerSubCrate,
+    /// A directory under the sub-crate's `target/`, one per feature set (named after a hash of the
+    /// features, of [TaskSettings::no_default_features] and [TaskSettings::all_features], and of
+    /// [TaskSettings::build_env], which may change the build, too).
+    /// Builds of the same sub-crate with different feature sets run in parallel, and each keeps
+    /// its artifacts, at the cost of disk space and of building dependencies once per feature set.
+    PerFeatureSet,
+}
+
+/// Directory (under a sub-crate's `target/`) of [TargetDir::PerFeatureSet] directories.
+const FEATURE_SET_TARGET_DIRS: &str = "feature-sets";
+
+/// The `--target-dir` for a task, if any (as per [TaskSettings::target_dir]).
+pub(crate) fn target_dir<'a, S>(
+    manifest_path: &Path,
     features: &Features<'a, S>,
-) -> DynErrResult<ChildProcess>
+    settings: &TaskSettings,
+) -> Option<PathBuf>
 where
     S: Borrow<str> + 'a + ?Sized,
-    //S: 'a + ?Sized,
     &'a S: Borrow<str>,
 {
-    let manifest_path = manifest_path_for_subdir(parent_dir, sub_dir);
-    let binary_crate = binary_crate.borrow();
-    let mut binary = TestBinary::relative_to_parent(binary_crate, &manifest_path);
-    binary.with_profile("dev");
-    for feature in features {
-        binary.with_feature(feature.borrow());
-    }
-    // @TODO DOC if we don't paralellize the tested feature combinations fully, then apply
-    // .with_feature(...) once per feature; re-build in the same folder (per the same
-    // channel/sequence of run, but stop on the first error (or warning), unless configured
-    // otherwise.
-    match binary.build() {
-        Ok(path) => {
-            let mut command = Command::new(path);
-            command.stdout(Stdio::piped());
-            command.stderr(Stdio::piped());
-            //command.env("RUST_TEST_TIME_INTEGRATION", "3600000");
-            println!(
-                "Starting a process under {}/ binary crate {}.",
-                sub_dir.borrow(),
-                binary_crate
+    let sub_crate_target = || manifest_path.with_file_name("target");
+    match settings.target_dir {
+        TargetDir::Shared => None,
+        TargetDir::PerSubCrate => Some(sub_crate_target()),
+        TargetDir::PerFeatureSet => {
+            let mut features: Vec<&str> = features.iter().map(|feature| feature.borrow()).collect();
+            features.sort_unstable();
+            features.dedup();
+            // Sorted by variable; a later value of the same variable wins (as in the build).
+            let build_env: BTreeMap<&str, &str> = settings
+                .build_env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            let build_env: Vec<String> = build_env
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            let key = format!(
+                "{}|{}|{}|{}",
+                features.join(","),
+                settings.no_default_features,
+                settings.all_features,
+                build_env.join("\0")
             );
-            return Ok(command.spawn()?);
+            Some(
+                sub_crate_target()
+                    .join(FEATURE_SET_TARGET_DIRS)
+                    .join(format!("{:016x}", fnv1a(key.as_bytes()))),
+            )
+        }
+    }
+}
+
+/// 64-bit FNV-1a hash. (Unlike [std::collections::hash_map::DefaultHasher], it's stable across Rust
+/// versions, so the directory names stay the same.)
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+/// The target directory that a build ends up in: its `--target-dir` (if any), or else
+/// `CARGO_TARGET_DIR` (of [TaskSettings::build_env], or else of this process), or else the
+/// sub-crate's `target/`. Made absolute, so that it can be compared.
+pub(crate) fn effective_target_dir(
+    manifest_path: &Path,
+    target_dir: Option<&Path>,
+    settings: &TaskSettings,
+) -> PathBuf {
+    let target_dir = match target_dir {
+        Some(target_dir) => target_dir.to_owned(),
+        None => settings
+            .build_env
+            .iter()
+            .rev()
+            .find(|(key, _)| key == CARGO_TARGET_DIR)
+            .map(|(_, value)| PathBuf::from(value))
+            .or_else(|| env::var_os(CARGO_TARGET_DIR).map(PathBuf::from))
+            .unwrap_or_else(|| manifest_path.with_file_name("target")),
+    };
+    match env::current_dir() {
+        Ok(current_dir) => current_dir.join(target_dir),
+        Err(_) => target_dir,
+    }
+}
+
+const CARGO_TARGET_DIR: &str = "CARGO_TARGET_DIR";
+
+/// Executables (their target directory, as per [effective_target_dir], and their binary name) that
+/// are being built, or that have been built but not started yet. See [ExecutableLease]. (An
+/// [Option], because [HashSet::new] is not `const`.)
+static LEASED_EXECUTABLES: Mutex<Option<HashSet<LeaseKey>>> = Mutex::new(None);
+
+/// Effective target directory, and binary name.
+type LeaseKey = (PathBuf, String);
+
+/// Exclusive use of an executable's path, from the start of its build until the executable has
+/// been started (or until the build has failed).
+///
+/// Cargo puts the executable at the same path (under the target directory), regardless of the
+/// sub-crate and of the features it was built with. So in the meantime no other task (in any
+/// group or thread of this process) may rebuild it under the same target directory, whichever
+/// [TargetDir] led there. (Cargo serializes builds under the same target directory anyway.) Builds
+/// under different target directories, or of binaries with different names, don't wait for each
+/// other.
+pub(crate) struct ExecutableLease(LeaseKey);
+impl ExecutableLease {
+    /// Return [None] if the executable is leased already.
+    pub fn try_new(target_dir: PathBuf, binary_name: String) -> Option<Self> {
+        let key = (target_dir, binary_name);
+        let mut leased = LEASED_EXECUTABLES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if leased.get_or_insert_with(HashSet::new).insert(key.clone()) {
+            Some(Self(key))
+        } else {
+            None
+        }
+    }
+}
+impl Drop for ExecutableLease {
+    fn drop(&mut self) {
+        let mut leased = LEASED_EXECUTABLES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(leased) = leased.as_mut() {
+            leased.remove(&self.0);
+        }
+    }
+}
+
+/// The jobserver shared by builds of all groups (and threads) of this process. See [jobserver].
+static JOBSERVER: Mutex<Option<Client>> = Mutex::new(None);
+
+/// The jobserver inherited from an outer `make` (through `MAKEFLAGS` or `CARGO_MAKEFLAGS`), if any.
+/// (`cargo test` doesn't pass its jobserver to test binaries.) Otherwise our own jobserver, with
+/// one token less than [std::thread::available_parallelism] (see [BuildToken::Implicit]).
+///
+/// Each build holds a [BuildToken] while it runs, and the build's `cargo` gets access to the
+/// jobserver (for its `rustc` jobs). Hence parallel builds don't overload the machine.
+pub fn jobserver() -> IoResult<Client> {
+    let mut jobserver = JOBSERVER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(client) = &*jobserver {
+        return Ok(client.clone());
+    }
+    // SAFETY: The inherited file descriptors (if any) are not used by anything else in this
+    // process. We take them only once, since we keep the client.
+    let client = match unsafe { Client::from_env() } {
+        Some(client) => client,
+        None => Client::new(
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1)
+                - 1,
+        )?,
+    };
+    *jobserver = Some(client.clone());
+    Ok(client)
+}
+
+/// Whether a build of this process holds [BuildToken::Implicit].
+static IMPLICIT_TOKEN_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Permission for one build to run, as per [jobserver]. Released when dropped.
+pub(crate) enum BuildToken {
+    /// Like any jobserver client, this process has one implicit token: one of its builds may run
+    /// without taking a token from the jobserver. (Otherwise, if all tokens of an inherited
+    /// jobserver were taken by other processes, no build of ours could ever start.)
+    Implicit,
+    /// A token from the jobserver, released when dropped.
+    Acquired { _token: Acquired },
+}
+impl BuildToken {
+    /// [BuildToken::Implicit], unless another build of this process holds it. Otherwise a token
+    /// from the jobserver, if it has any available right now. Otherwise [None].
+    pub fn try_acquire(jobserver: &Client) -> IoResult<Option<Self>> {
+        if IMPLICIT_TOKEN_TAKEN
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            return Ok(Some(Self::Implicit));
         }
-        Err(e) => Err(Box::new(e)),
+        Ok(jobserver
+            .try_acquire()?
+            .map(|token| Self::Acquired { _token: token }))
+    }
+}
+impl Drop for BuildToken {
+    fn drop(&mut self) {
+        if let Self::Implicit = self {
+            IMPLICIT_TOKEN_TAKEN.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Start `cargo build` of the binary crate, with the given features. Its `stdout` (JSON messages)
+/// and `stderr` (rendered diagnostics) are piped.
+///
+/// If given a jobserver, `cargo` uses it (and the caller should hold a token for it).
+///
+/// `binary_name` is as per [binary_name].
+///
+/// Fail (without starting anything) if there is no such manifest.
+pub(crate) fn build<'a, S>(
+    manifest_path: &Path,
+    binary_name: &str,
+    features: &Features<'a, S>,
+    settings: &TaskSettings,
+    target_dir: Option<&Path>,
+    jobserver: Option<&Client>,
+) -> DynErrResult<ChildProcess>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    check_manifest_exists(manifest_path)?;
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let mut command = Command::new(cargo);
+    command
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(manifest_path)
+        .args(["--bin", binary_name])
+        .args([
+            "--profile",
+            settings.profile.as_deref().unwrap_or(DEFAULT_PROFILE),
+        ])
+        .arg("--message-format=json-render-diagnostics");
+    if !features.is_empty() {
+        let features: Vec<&str> = features.iter().map(|feature| feature.borrow()).collect();
+        command.args(["--features", &features.join(",")]);
+    }
+    if settings.no_default_features {
+        command.arg("--no-default-features");
+    }
+    if settings.all_features {
+        command.arg("--all-features");
+    }
+    if let Some(target_dir) = target_dir {
+        command.arg("--target-dir").arg(target_dir);
+    }
+    command.envs(settings.build_env.iter().map(|(key, value)| (key, value)));
+    if let Some(jobserver) = jobserver {
+        jobserver.configure_make(&mut command);
+    }
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    kill::own_process_group(&mut command);
+    println!(
+        "Building {} binary crate {binary_name}.",
+        manifest_path.display()
+    );
+    Ok(command.spawn()?)
+}
+
+/// Name of the binary crate's target: for [BinaryCrateName::Main] that's the package name (as per
+/// [manifest::read_package_name]).
+///
+/// Fail if there is no such manifest.
+pub(crate) fn binary_name<'a, S>(
+    manifest_path: &Path,
+    binary_crate: &BinaryCrateName<'a, S>,
+) -> DynErrResult<String>
+where
+    S: Borrow<str> + 'a + ?Sized,
+    &'a S: Borrow<str>,
+{
+    check_manifest_exists(manifest_path)?;
+    match binary_crate {
+        BinaryCrateName::Main => manifest::read_package_name(manifest_path),
+        BinaryCrateName::Other(name) => Ok((*name).borrow().to_owned()),
+    }
+}
+
+fn check_manifest_exists(manifest_path: &Path) -> DynErrResult<()> {
+    if manifest_path.is_file() {
+        Ok(())
+    } else {
+        Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No manifest at {}.", manifest_path.display()),
+        )))
+    }
+}
+
+/// Collects `stdout` and `stderr` of a running `cargo build` (in helper threads), so that cargo
+/// doesn't block on a full pipe. (Unlike the executables under test, cargo may write a lot of JSON
+/// messages.)
+pub(crate) struct BuildCapture {
+    stdout: JoinHandle<IoResult<Vec<u8>>>,
+    stderr: JoinHandle<IoResult<Vec<u8>>>,
+    started: Instant,
+}
+impl BuildCapture {
+    /// Take the (piped) `stdout` and `stderr` of the build (as started by [build]).
+    pub fn new(child: &mut ChildProcess) -> Self {
+        Self {
+            stdout: read_in_thread(child.stdout.take().unwrap()),
+            stderr: read_in_thread(child.stderr.take().unwrap()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Collect the output, once the build has exited with the given status.
+    pub fn finish(self, status: ExitStatus) -> IoResult<BuildOutput> {
+        let duration = self.started.elapsed();
+        let stdout = self
+            .stdout
+            .join()
+            .expect("A pipe reading thread panicked.")?;
+        let stderr = self
+            .stderr
+            .join()
+            .expect("A pipe reading thread panicked.")?;
+        Ok((
+            ProcessOutput {
+                status,
+                stdout,
+                stderr,
+            },
+            duration,
+        ))
+    }
+}
+
+fn read_in_thread<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<IoResult<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        pipe.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+}
+
+/// Find the executable of the binary crate in JSON messages of a successful `cargo build`.
+pub(crate) fn executable_path(
+    build_output: &ProcessOutput,
+    binary_crate: &str,
+) -> DynErrResult<PathBuf> {
+    for line in build_output.stdout.split(|&byte| byte == b'\n') {
+        let message: serde_json::Value = match serde_json::from_slice(line) {
+            Ok(message) => message,
+            // Cargo may pass through any non-JSON output (of build scripts, for example).
+            Err(_) => continue,
+        };
+        if message["reason"] == "compiler-artifact" && message["target"]["name"] == binary_crate {
+            if let Some(executable) = message["executable"].as_str() {
+                return Ok(PathBuf::from(executable));
+            }
+        }
+    }
+    Err(Box::new(NoExecutable {
+        binary_crate: binary_crate.to_owned(),
+    }))
+}
+
+/// Start the built executable, with its `stdout` and `stderr` piped, and with its environment,
+/// arguments and working directory as per `settings`.
+pub(crate) fn run(
+    executable: &Path,
+    info: &str,
+    settings: &TaskSettings,
+) -> IoResult<ChildProcess> {
+    let mut command = Command::new(executable);
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    kill::own_process_group(&mut command);
+    if settings.clear_run_env {
+        command.env_clear();
+    }
+    command.envs(settings.run_env.iter().map(|(key, value)| (key, value)));
+    command.args(&settings.args);
+    if let Some(working_dir) = &settings.working_dir {
+        command.current_dir(working_dir);
     }
+    println!("Starting {info}.");
+    command.spawn()
 }
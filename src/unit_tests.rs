@@ -1,4 +1,36 @@
+use crate::{
+    group::{ParallelTask, TaskSettings},
+    indicators::BinaryCrateName,
+};
+
+mod combinations_tests;
+mod constraints_tests;
+mod group_of_sequences_of_groups_tests;
 mod group_tests;
 mod indicators_tests;
 mod lib_tests;
+mod manifest_tests;
 mod output_tests;
+mod reap_tests;
+mod run_tests;
+mod sequence_of_groups_tests;
+
+/// Parent directory of the sub-crates that the tests build and run.
+const PARENT_DIR: &str = "testbins";
+/// The sub-crate (and its binary crate) whose behavior depends on its features.
+const FIXTURE: &str = "fixture";
+const FIXTURE_CRATE: BinaryCrateName<'static, str> = BinaryCrateName::Other(FIXTURE);
+
+/// A task of [FIXTURE_CRATE], with its features (comma-separated) as its info.
+fn fixture_task<M>(
+    features: Vec<&'static str>,
+    settings: TaskSettings,
+    meta: M,
+) -> ParallelTask<'static, str, M> {
+    let info = features.join(",");
+    (FIXTURE, &FIXTURE_CRATE, features, settings, info, meta)
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| (*item).to_owned()).collect()
+}
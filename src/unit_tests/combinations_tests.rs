@@ -0,0 +1,352 @@
+use super::{strings, FIXTURE, FIXTURE_CRATE, PARENT_DIR};
+use crate::{
+    combinations::{self, CombinationSettings, Sampling},
+    group::{GroupSettings, TaskSettings},
+    indicators::{BinaryCrateName, GroupEnd},
+    manifest::FeatureTable,
+    output, run,
+};
+use core::num::NonZeroUsize;
+
+#[test]
+fn powerset_is_ordered_by_size() {
+    let features = strings(&["a", "b", "c"]);
+    assert_eq!(
+        combinations::powerset(&features),
+        vec![
+            vec![],
+            vec!["a"],
+            vec!["b"],
+            vec!["c"],
+            vec!["a", "b"],
+            vec!["a", "c"],
+            vec!["b", "c"],
+            vec!["a", "b", "c"],
+        ]
+    );
+    assert_eq!(combinations::powerset(&[]), vec![Vec::<&str>::new()]);
+}
+
+#[test]
+fn parallel_tasks_run_each_feature_set() {
+    let features = strings(&["a", "b"]);
+    let tasks = combinations::parallel_tasks(
+        FIXTURE,
+        &FIXTURE_CRATE,
+        combinations::powerset(&features),
+        None,
+        &TaskSettings::default(),
+    );
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    // The powerset, and the "no defaults" baseline.
+    assert_eq!(outputs.len(), 5);
+    for (output, error) in &outputs {
+        assert!(!output::has_error(output, error));
+        let (process_output, _, features, _) = output.as_ref().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&process_output.as_ref().unwrap().stdout),
+            format!("Features: {features:?}\n")
+        );
+    }
+}
+
+#[test]
+fn default_features_per_task() {
+    let binary_crate = BinaryCrateName::Other("defaults");
+    let all_features = TaskSettings {
+        all_features: true,
+        ..TaskSettings::default()
+    };
+    let tasks = combinations::parallel_tasks(
+        "defaults",
+        &binary_crate,
+        vec![vec![], vec!["off"]],
+        None,
+        &TaskSettings::default(),
+    )
+    .into_iter()
+    .chain(combinations::parallel_tasks(
+        "defaults",
+        &binary_crate,
+        vec![vec![]],
+        None,
+        &all_features,
+    ))
+    .collect();
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let mut stdouts: Vec<(String, String)> = outputs
+        .iter()
+        .map(|(output, error)| {
+            assert!(!output::has_error(output, error));
+            let (process_output, info, _, _) = output.as_ref().unwrap();
+            (
+                info.clone(),
+                String::from_utf8_lossy(&process_output.as_ref().unwrap().stdout).into_owned(),
+            )
+        })
+        .collect();
+    stdouts.sort();
+    assert_eq!(
+        stdouts,
+        vec![
+            (
+                "defaults/ binary crate defaults with features []".to_owned(),
+                "Features: [\"on\"]\n".to_owned()
+            ),
+            (
+                "defaults/ binary crate defaults with features [], all features".to_owned(),
+                "Features: [\"on\", \"off\"]\n".to_owned()
+            ),
+            (
+                "defaults/ binary crate defaults with features [], no default features".to_owned(),
+                "Features: []\n".to_owned()
+            ),
+            (
+                "defaults/ binary crate defaults with features [off]".to_owned(),
+                "Features: [\"on\", \"off\"]\n".to_owned()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn parallel_tasks_for_profiles_multiply_feature_sets() {
+    let binary_crate = BinaryCrateName::Other("defaults");
+    let profiles = strings(&["release", "custom"]);
+    let tasks = combinations::parallel_tasks_for_profiles(
+        "defaults",
+        &binary_crate,
+        vec![vec!["off"]],
+        &profiles,
+        None,
+        &TaskSettings::default(),
+    )
+    .into_iter()
+    .chain(combinations::parallel_tasks(
+        "defaults",
+        &binary_crate,
+        vec![],
+        None,
+        &TaskSettings::default(),
+    ))
+    .collect::<Vec<_>>();
+    let profiles_of_tasks: Vec<Option<&str>> =
+        tasks.iter().map(|task| task.3.profile.as_deref()).collect();
+    assert_eq!(
+        profiles_of_tasks,
+        vec![
+            Some("release"),
+            Some("release"),
+            Some("custom"),
+            Some("custom"),
+            None
+        ]
+    );
+
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    assert_eq!(outputs.len(), 5);
+    for (output, error) in &outputs {
+        assert!(!output::has_error(output, error));
+        let (_, info, _, build_output) = output.as_ref().unwrap();
+        let profile = info.split(", profile ").nth(1).unwrap_or("dev");
+        let stderr = String::from_utf8_lossy(&build_output.as_ref().unwrap().0.stderr);
+        assert!(stderr.contains(&format!("`{profile}` profile")), "{stderr}");
+    }
+}
+
+#[test]
+fn combinations_limited_grouped_and_always_on() {
+    let features = strings(&["a", "b", "c", "d", "e"]);
+    let settings = CombinationSettings {
+        max_size: Some(1),
+        groups: vec![vec!["c".to_owned(), "b".to_owned()]],
+        always_on: vec!["e".to_owned()],
+        ..CombinationSettings::default()
+    };
+    assert_eq!(
+        combinations::combinations(&features, &settings),
+        vec![
+            vec!["e"],
+            vec!["e", "a"],
+            vec!["e", "c", "b"],
+            vec!["e", "d"]
+        ]
+    );
+
+    let settings = CombinationSettings {
+        max_size: Some(2),
+        ..CombinationSettings::default()
+    };
+    let combinations = combinations::combinations(&features, &settings);
+    // 1 + 5 + 10
+    assert_eq!(combinations.len(), 16);
+    assert!(combinations
+        .iter()
+        .all(|combination| combination.len() <= 2));
+}
+
+fn covering(strength: usize, seed: u64) -> CombinationSettings {
+    CombinationSettings {
+        sampling: Sampling::Covering {
+            strength: NonZeroUsize::new(strength).unwrap(),
+            seed,
+        },
+        ..CombinationSettings::default()
+    }
+}
+
+/// Whether every `strength` features have all their on/off states in some combination.
+fn is_covering(features: &[String], combinations: &[Vec<&str>], strength: usize) -> bool {
+    let features: Vec<&str> = features.iter().map(String::as_str).collect();
+    combinations::combinations_of_size(&features, strength)
+        .iter()
+        .all(|tuple| {
+            (0..1usize << strength).all(|states| {
+                combinations.iter().any(|combination| {
+                    tuple.iter().enumerate().all(|(bit, feature)| {
+                        combination.contains(feature) == (states & (1 << bit) != 0)
+                    })
+                })
+            })
+        })
+}
+
+#[test]
+fn covering_arrays_cover_all_tuples() {
+    let features: Vec<String> = (0..10).map(|i| format!("f{i}")).collect();
+
+    let settings = covering(2, 1);
+    let pairwise = combinations::combinations(&features, &settings);
+    assert!(is_covering(&features, &pairwise, 2));
+    // Much fewer than the powerset (1024).
+    assert!(pairwise.len() <= 20, "{}", pairwise.len());
+
+    let settings = covering(3, 1);
+    let three_wise = combinations::combinations(&features, &settings);
+    assert!(is_covering(&features, &three_wise, 3));
+
+    // Strength above the number of features means the powerset.
+    let settings = covering(5, 1);
+    let all = combinations::combinations(&features[..3], &settings);
+    assert_eq!(all.len(), 8);
+}
+
+#[test]
+fn covering_arrays_are_deterministic_per_seed() {
+    let features: Vec<String> = (0..8).map(|i| format!("f{i}")).collect();
+    let settings = covering(2, 42);
+    assert_eq!(
+        combinations::combinations(&features, &settings),
+        combinations::combinations(&features, &settings)
+    );
+}
+
+#[test]
+fn parallel_tasks_for_profiles_and_args() {
+    let variants = combinations::settings_for_args(
+        &combinations::settings_for_profiles(
+            &[TaskSettings::default()],
+            &strings(&["dev", "release"]),
+        ),
+        &[strings(&["x"]), strings(&["y"])],
+    );
+    let tasks = combinations::parallel_tasks_for_settings(
+        FIXTURE,
+        &FIXTURE_CRATE,
+        vec![vec!["a"]],
+        None,
+        &variants,
+    );
+    let infos: Vec<&str> = tasks.iter().map(|task| task.4.as_str()).collect();
+    let info = |features: &str, rest: &str| {
+        format!("fixture/ binary crate fixture with features [{features}]{rest}")
+    };
+    assert_eq!(
+        infos,
+        vec![
+            info("", ", no default features, profile dev, args [\"x\"]"),
+            info("a", ", profile dev, args [\"x\"]"),
+            info("", ", no default features, profile release, args [\"x\"]"),
+            info("a", ", profile release, args [\"x\"]"),
+            info("", ", no default features, profile dev, args [\"y\"]"),
+            info("a", ", profile dev, args [\"y\"]"),
+            info("", ", no default features, profile release, args [\"y\"]"),
+            info("a", ", profile release, args [\"y\"]"),
+        ]
+    );
+}
+
+#[test]
+fn dedupe_by_feature_graph() {
+    let table: FeatureTable = [
+        ("default", vec![]),
+        ("a", vec!["b"]),
+        ("b", vec![]),
+        ("c", vec!["dep:serde"]),
+    ]
+    .into_iter()
+    .map(|(feature, enables)| (feature.to_owned(), strings(&enables)))
+    .collect();
+    let features = strings(&["a", "b", "c"]);
+    assert_eq!(
+        combinations::dedupe(combinations::powerset(&features), &table, false),
+        vec![
+            vec![],
+            vec!["a"],
+            vec!["b"],
+            vec!["c"],
+            vec!["a", "c"],
+            vec!["b", "c"],
+        ]
+    );
+
+    // `b` is a default feature, so it makes no difference unless default features are off.
+    let table: FeatureTable = [("default", vec!["b"]), ("a", vec![]), ("b", vec![])]
+        .into_iter()
+        .map(|(feature, enables)| (feature.to_owned(), strings(&enables)))
+        .collect();
+    let features = strings(&["a", "b"]);
+    assert_eq!(
+        combinations::dedupe(combinations::powerset(&features), &table, false),
+        vec![vec![], vec!["a"]]
+    );
+    assert_eq!(
+        combinations::dedupe(combinations::powerset(&features), &table, true),
+        vec![vec![], vec!["a"], vec!["b"], vec!["a", "b"]]
+    );
+}
+
+#[test]
+fn no_baseline_without_default_feature() {
+    let binary_crate = BinaryCrateName::Other("other");
+    let table: FeatureTable = [("a".to_owned(), vec![])].into_iter().collect();
+    let tasks = combinations::parallel_tasks(
+        "other",
+        &binary_crate,
+        vec![vec![], vec!["a"]],
+        Some(&table),
+        &TaskSettings::default(),
+    );
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks.iter().all(|task| !task.3.no_default_features));
+}
@@ -0,0 +1,130 @@
+use super::strings;
+use crate::{
+    combinations::{self, CombinationSettings},
+    constraints::{Constraints, OnViolation, Violation},
+    manifest::FeatureTable,
+};
+
+fn constraints() -> Constraints {
+    Constraints {
+        mutually_exclusive: vec![strings(&["backend-a", "backend-b"])],
+        requires: vec![("tls".to_owned(), strings(&["net"]))],
+        forbidden: vec![strings(&["net", "no-std"])],
+    }
+}
+
+#[test]
+fn violations() {
+    let constraints = constraints();
+    assert_eq!(
+        constraints.violation(&["backend-a", "tls", "net"], None),
+        None
+    );
+    assert_eq!(
+        constraints.violation(&["backend-a", "backend-b"], None),
+        Some(Violation::MutuallyExclusive(strings(&[
+            "backend-a",
+            "backend-b"
+        ])))
+    );
+    assert_eq!(
+        constraints.violation(&["tls"], None),
+        Some(Violation::Requires {
+            feature: "tls".to_owned(),
+            missing: strings(&["net"])
+        })
+    );
+    assert_eq!(
+        constraints.violation(&["no-std", "net"], None),
+        Some(Violation::Forbidden(strings(&["net", "no-std"])))
+    );
+}
+
+#[test]
+fn combinations_skip_or_keep_violations() {
+    let features = strings(&["backend-a", "backend-b", "net", "no-std", "tls"]);
+    let settings = CombinationSettings {
+        constraints: constraints(),
+        ..CombinationSettings::default()
+    };
+    let valid = combinations::combinations(&features, &settings);
+    assert!(valid
+        .iter()
+        .all(|combination| settings.constraints.violation(combination, None).is_none()));
+    assert!(valid.contains(&vec!["backend-a", "net", "tls"]));
+    assert!(!valid.contains(&vec!["backend-a", "backend-b"]));
+
+    let settings = CombinationSettings {
+        on_violation: OnViolation::Keep,
+        ..settings
+    };
+    assert_eq!(combinations::combinations(&features, &settings).len(), 32);
+}
+
+/// Features of the sub-crate that [constraints] apply to, with the given default features.
+fn table(default: &[&str]) -> FeatureTable {
+    [
+        ("default", default.to_vec()),
+        ("backend-a", vec![]),
+        ("backend-b", vec![]),
+        ("all-backends", vec!["backend-a", "backend-b"]),
+        ("net", vec![]),
+        ("no-std", vec![]),
+        ("tls", vec!["net"]),
+    ]
+    .into_iter()
+    .map(|(feature, enables)| (feature.to_owned(), strings(&enables)))
+    .collect()
+}
+
+#[test]
+fn violations_of_effective_features() {
+    let table = table(&[]);
+    let constraints = constraints();
+    assert_eq!(constraints.violation(&["all-backends"], None), None);
+    assert_eq!(
+        constraints.violation(&["all-backends"], Some(&table)),
+        Some(Violation::MutuallyExclusive(strings(&[
+            "backend-a",
+            "backend-b"
+        ])))
+    );
+    assert!(constraints.violation(&["tls"], None).is_some());
+    assert_eq!(constraints.violation(&["tls"], Some(&table)), None);
+}
+
+#[test]
+fn combinations_skip_violations_of_effective_features() {
+    let features = strings(&["all-backends", "no-std", "tls"]);
+    let settings = CombinationSettings {
+        constraints: constraints(),
+        ..CombinationSettings::default()
+    };
+    assert_eq!(
+        combinations::combinations(&features, &settings),
+        vec![
+            vec![],
+            vec!["all-backends"],
+            vec!["no-std"],
+            vec!["all-backends", "no-std"]
+        ]
+    );
+
+    // `net` is a default feature, so `no-std` is forbidden, unless without default features.
+    let settings = CombinationSettings {
+        table: Some(table(&["net"])),
+        ..settings
+    };
+    assert_eq!(
+        combinations::combinations(&features, &settings),
+        vec![vec![], vec!["tls"]]
+    );
+    let settings = CombinationSettings {
+        no_default_features: true,
+        ..settings
+    };
+    assert_eq!(
+        combinations::combinations(&features, &settings),
+        vec![vec![], vec!["no-std"], vec!["tls"]]
+    );
+}
@@ -0,0 +1,80 @@
+use super::{fixture_task, PARENT_DIR};
+use crate::{
+    group::{GroupSettings, TaskSettings},
+    group_of_sequences_of_groups::{self, GraphTask, PlanError, SkippedDueTo, TaskGraph},
+    output,
+};
+
+fn graph_task(
+    features: Vec<&'static str>,
+    dependencies: Vec<usize>,
+) -> GraphTask<'static, str, ()> {
+    (
+        fixture_task(features, TaskSettings::default(), ()),
+        dependencies,
+    )
+}
+
+#[test]
+fn plan_rejects_unknown_dependency() {
+    let result = TaskGraph::new(vec![
+        graph_task(vec![], vec![]),
+        graph_task(vec![], vec![2]),
+    ]);
+    assert_eq!(
+        result.err(),
+        Some(PlanError::UnknownDependency {
+            task: 1,
+            dependency: 2
+        })
+    );
+}
+
+#[test]
+fn plan_detects_cycle() {
+    let result = TaskGraph::new(vec![
+        graph_task(vec![], vec![]),
+        graph_task(vec![], vec![0, 3]),
+        graph_task(vec![], vec![1]),
+        graph_task(vec![], vec![2]),
+    ]);
+    match result.err() {
+        Some(PlanError::Cycle(mut cycle)) => {
+            cycle.sort();
+            assert_eq!(cycle, vec![1, 2, 3]);
+        }
+        _ => panic!("Expected a cycle."),
+    }
+}
+
+#[test]
+fn failure_skips_only_dependents() {
+    let graph = TaskGraph::new(vec![
+        graph_task(vec!["a"], vec![]),
+        graph_task(vec!["fail"], vec![]),
+        graph_task(vec!["b"], vec![0]),
+        graph_task(vec!["a", "b"], vec![1]),
+        graph_task(vec!["a", "stderr"], vec![3, 0]),
+    ])
+    .unwrap();
+    let results =
+        group_of_sequences_of_groups::run_task_graph(graph, PARENT_DIR, &GroupSettings::default())
+            .unwrap();
+
+    let failed: Vec<_> = results
+        .iter()
+        .map(|(output, error)| output::has_error(output, error))
+        .collect();
+    assert_eq!(failed, vec![false, true, false, true, true]);
+
+    for (output, error) in &results[3..] {
+        assert!(output.as_ref().unwrap().0.is_none());
+        let skipped = error
+            .as_ref()
+            .unwrap()
+            .downcast_ref::<SkippedDueTo>()
+            .unwrap();
+        assert_eq!(skipped.task, 1);
+        assert_eq!(skipped.info, "fail");
+    }
+}
@@ -1,4 +1,506 @@
+use super::{fixture_task, FIXTURE, PARENT_DIR};
 use crate::{
-    group,
-    indicators::{BinaryCrateName, GroupEnd},
+    group::{
+        self, ChildInfoMeta, GroupOfChildren, GroupSettings, ParallelTasks, Stopped, TaskQueue,
+        TaskSettings, Termination, TimedOut,
+    },
+    indicators::{BinaryCrateName, GroupEnd, SpawningMode},
+    kill, output,
+    task::{self, ExecutableLease, TargetDir},
 };
+use core::num::NonZeroUsize;
+use core::time::Duration;
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// Spawn `/usr/bin/sh -c <script>` as a child, with its `meta` being the given script.
+fn insert_child<'s>(
+    children: &mut GroupOfChildren<&'s str>,
+    script: &'s str,
+    timeout: Option<Duration>,
+) {
+    let mut command = Command::new("/usr/bin/sh");
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.arg("-c").arg(script);
+    kill::own_process_group(&mut command);
+
+    let child = command.spawn().unwrap();
+    let child_id = child.id().into();
+    let mut child_info_meta = ChildInfoMeta::new(child, script.to_owned(), script);
+    if let Some(timeout) = timeout {
+        child_info_meta = child_info_meta.with_timeout(timeout);
+    }
+    children.insert(child_id, child_info_meta);
+}
+
+fn children<'s>(scripts: &[&'s str]) -> GroupOfChildren<&'s str> {
+    let mut children = GroupOfChildren::new();
+    for script in scripts {
+        insert_child(&mut children, script, None);
+    }
+    children
+}
+
+fn no_queue<'s>() -> TaskQueue<'s, str, &'s str> {
+    TaskQueue::new(Vec::new(), "", &GroupSettings::default())
+}
+
+/// Two tasks in flight, and two builds (even on a single CPU). Without a jobserver, not to depend
+/// on its tokens taken by any other tests.
+fn two_in_flight() -> GroupSettings {
+    GroupSettings {
+        max_in_flight: NonZeroUsize::new(2).unwrap(),
+        max_builds_in_flight: NonZeroUsize::new(2).unwrap(),
+        use_jobserver: false,
+        ..GroupSettings::default()
+    }
+}
+
+const OK: &str = "echo ok";
+const FAIL_SOON: &str = "sleep 0.2; exit 1";
+const OK_LATER: &str = "sleep 1; echo later";
+
+#[test]
+fn life_cycle_loop_process_all() {
+    let children = children(&[OK, FAIL_SOON, OK_LATER]);
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut no_queue(),
+        &GroupEnd::ProcessAll,
+    )
+    .unwrap();
+
+    let metas: Vec<_> = outputs
+        .iter()
+        .map(|(output, _)| output.as_ref().unwrap().2)
+        .collect();
+    assert_eq!(metas, vec![OK, FAIL_SOON, OK_LATER]);
+    let errors = outputs
+        .iter()
+        .filter(|(output, error)| output::has_error(output, error))
+        .count();
+    assert_eq!(errors, 1);
+}
+
+#[test]
+fn life_cycle_loop_on_failure_finish_active() {
+    let children = children(&[FAIL_SOON, OK_LATER]);
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut no_queue(),
+        &GroupEnd::OnFailureFinishActive,
+    )
+    .unwrap();
+
+    let metas: Vec<_> = outputs
+        .iter()
+        .map(|(output, _)| output.as_ref().unwrap().2)
+        .collect();
+    assert_eq!(metas, vec![FAIL_SOON, OK_LATER]);
+}
+
+#[test]
+fn life_cycle_loop_on_failure_stop_all() {
+    const IGNORING_TERM: &str = "trap '' TERM; exec sleep 30";
+    let children = children(&[FAIL_SOON, "exec sleep 30", IGNORING_TERM]);
+    let mut queue = TaskQueue::new(
+        Vec::new(),
+        "",
+        &GroupSettings {
+            stop_grace_period: Duration::from_millis(300),
+            ..GroupSettings::default()
+        },
+    );
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut queue,
+        &GroupEnd::OnFailureStopAll,
+    )
+    .unwrap();
+
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].0.as_ref().unwrap().2, FAIL_SOON);
+    assert!(outputs[0].1.is_none());
+
+    let mut terminations: Vec<_> = outputs[1..]
+        .iter()
+        .map(|(output, error)| {
+            let (process_output, _, meta, _) = output.as_ref().unwrap();
+            assert!(process_output.is_none());
+            let stopped = error.as_ref().unwrap().downcast_ref::<Stopped>().unwrap();
+            (*meta, stopped.termination)
+        })
+        .collect();
+    terminations.sort_by_key(|(meta, _)| *meta);
+    assert_eq!(
+        terminations,
+        vec![
+            ("exec sleep 30", Termination::OnTerm),
+            (IGNORING_TERM, Termination::OnKill)
+        ]
+    );
+}
+
+/// Tasks under a non-existing sub dir, so that they fail to start (without building anything).
+fn non_existing_tasks<'s>(
+    binary_crate: &'s BinaryCrateName<'s, str>,
+) -> ParallelTasks<'s, str, u8> {
+    (0..3)
+        .map(|i| {
+            (
+                "non-existing-sub-dir",
+                binary_crate,
+                vec![],
+                TaskSettings::default(),
+                format!("task {i}"),
+                i,
+            )
+        })
+        .collect()
+}
+
+fn settings_max_in_flight_one() -> GroupSettings {
+    GroupSettings {
+        max_in_flight: NonZeroUsize::new(1).unwrap(),
+        ..GroupSettings::default()
+    }
+}
+
+/// Tasks that fail to start don't occupy any of [GroupSettings::max_in_flight] slots, so under
+/// [GroupEnd::ProcessAll] all queued tasks get (attempted to be) started.
+#[test]
+fn queued_tasks_start_under_process_all() {
+    let binary_crate = BinaryCrateName::Main;
+    let until = GroupEnd::ProcessAll;
+    let ((execution, start_errors), mut queue) = group::start_parallel_tasks(
+        non_existing_tasks(&binary_crate),
+        "",
+        &until,
+        &settings_max_in_flight_one(),
+    );
+    let metas: Vec<_> = start_errors
+        .iter()
+        .map(|(output, _)| output.as_ref().unwrap().2)
+        .collect();
+    assert_eq!(metas, vec![0, 1, 2]);
+    assert!(queue.is_empty());
+
+    let outputs = group::life_cycle_loop(execution, &mut queue, &until).unwrap();
+    assert!(outputs.is_empty());
+}
+
+#[test]
+fn queued_tasks_skipped_under_finish_active() {
+    let binary_crate = BinaryCrateName::Main;
+    let until = GroupEnd::OnFailureFinishActive;
+    let ((execution, start_errors), mut queue) = group::start_parallel_tasks(
+        non_existing_tasks(&binary_crate),
+        "",
+        &until,
+        &settings_max_in_flight_one(),
+    );
+    assert_eq!(start_errors.len(), 1);
+    assert_eq!(execution.1, SpawningMode::FinishActive);
+
+    let outputs = group::life_cycle_loop(execution, &mut queue, &until).unwrap();
+    assert!(outputs.is_empty());
+    let skipped: Vec<_> = queue
+        .into_skipped()
+        .into_iter()
+        .map(|task| task.5)
+        .collect();
+    assert_eq!(skipped, vec![1, 2]);
+}
+
+#[test]
+fn timed_out_child_is_killed_and_keeps_partial_output() {
+    const HANGING: &str = "echo partial; exec sleep 30";
+    let mut children = children(&[OK_LATER]);
+    insert_child(&mut children, HANGING, Some(Duration::from_millis(200)));
+
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut no_queue(),
+        &GroupEnd::OnFailureFinishActive,
+    )
+    .unwrap();
+
+    let (output, error) = &outputs[0];
+    let (process_output, _, meta, _) = output.as_ref().unwrap();
+    assert_eq!(*meta, HANGING);
+    assert_eq!(process_output.as_ref().unwrap().stdout, b"partial\n");
+    let timed_out = error.as_ref().unwrap().downcast_ref::<TimedOut>().unwrap();
+    assert_eq!(timed_out.after, Duration::from_millis(200));
+
+    // The other (active) child was not killed.
+    assert_eq!(outputs[1].0.as_ref().unwrap().2, OK_LATER);
+    assert!(outputs[1].1.is_none());
+}
+
+/// Whether the process is gone (or it's a zombie, waiting for its new parent to reap it).
+fn is_gone(pid: &str) -> bool {
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => stat.split_whitespace().nth(2) == Some("Z"),
+        Err(_) => true,
+    }
+}
+
+/// Whether no process (other than zombies) is left in the process group.
+fn is_group_gone(group: &str) -> bool {
+    fs::read_dir("/proc").unwrap().all(|entry| {
+        let stat = fs::read_to_string(entry.unwrap().path().join("stat")).unwrap_or_default();
+        // Skip the command (in parentheses, and possibly with spaces).
+        let fields: Vec<&str> = stat
+            .rsplit(')')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        // Fields after the command: state, parent ID, process group ID...
+        fields.get(2) != Some(&group) || fields.first() == Some(&"Z")
+    })
+}
+
+#[test]
+fn stop_all_kills_rest_of_process_group() {
+    // The child exits on SIGTERM, but its helper (in the same process group) ignores it.
+    const WITH_HELPER: &str = "(trap '' TERM; exec sleep 30) >/dev/null 2>&1 & exec sleep 30";
+    let children = children(&[FAIL_SOON, WITH_HELPER]);
+    let group = children
+        .iter()
+        .find(|(_, child_info_meta)| *child_info_meta.meta() == WITH_HELPER)
+        .map(|(child_id, _)| child_id.get().to_string())
+        .unwrap();
+    let mut queue = TaskQueue::new(
+        Vec::new(),
+        "",
+        &GroupSettings {
+            stop_grace_period: Duration::from_millis(300),
+            ..GroupSettings::default()
+        },
+    );
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut queue,
+        &GroupEnd::OnFailureStopAll,
+    )
+    .unwrap();
+
+    let stopped = outputs[1]
+        .1
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<Stopped>()
+        .unwrap();
+    assert_eq!(stopped.termination, Termination::OnTerm);
+    assert!(is_group_gone(&group));
+}
+
+#[test]
+fn timed_out_child_is_killed_with_its_process_group() {
+    // The helper (grandchild) keeps the pipe open, so collecting the output would block if the
+    // helper survived.
+    const WITH_HELPER: &str = "sleep 30 & echo $!; wait";
+    let mut children = GroupOfChildren::new();
+    insert_child(&mut children, WITH_HELPER, Some(Duration::from_millis(200)));
+
+    let outputs = group::life_cycle_loop(
+        (children, SpawningMode::ProcessAll),
+        &mut no_queue(),
+        &GroupEnd::ProcessAll,
+    )
+    .unwrap();
+
+    let process_output = outputs[0].0.as_ref().unwrap().0.as_ref().unwrap();
+    let helper_pid = String::from_utf8_lossy(&process_output.stdout)
+        .trim()
+        .to_owned();
+    assert!(is_gone(&helper_pid));
+}
+
+#[test]
+fn dropped_children_are_killed() {
+    let children = children(&["exec sleep 30"]);
+    let pid = children.keys().next().unwrap().get().to_string();
+    drop(children);
+    assert!(is_gone(&pid));
+}
+
+#[test]
+fn builds_are_limited_separately_from_runs() {
+    let other = BinaryCrateName::Other("other");
+    let tasks = vec![
+        fixture_task(vec![], TaskSettings::default(), 0),
+        (
+            "other",
+            &other,
+            vec![],
+            TaskSettings::default(),
+            "other".to_owned(),
+            1,
+        ),
+    ];
+    let settings = GroupSettings {
+        max_builds_in_flight: NonZeroUsize::new(1).unwrap(),
+        // Not to depend on jobserver tokens taken by any other tests.
+        use_jobserver: false,
+        ..GroupSettings::default()
+    };
+    let until = GroupEnd::ProcessAll;
+    let ((execution, start_errors), mut queue) =
+        group::start_parallel_tasks(tasks, PARENT_DIR, &until, &settings);
+    assert!(start_errors.is_empty());
+    assert_eq!(execution.0.len(), 1);
+    assert!(execution.0.values().all(ChildInfoMeta::is_building));
+    assert_eq!(queue.len(), 1);
+
+    let outputs = group::life_cycle_loop(execution, &mut queue, &until).unwrap();
+    assert_eq!(outputs.len(), 2);
+    assert!(outputs
+        .iter()
+        .all(|(output, error)| !output::has_error(output, error)));
+}
+
+#[test]
+fn per_feature_set_target_dirs_build_in_parallel() {
+    let task = |features: Vec<&'static str>| {
+        let settings = TaskSettings {
+            target_dir: TargetDir::PerFeatureSet,
+            ..TaskSettings::default()
+        };
+        fixture_task(features, settings, ())
+    };
+    let tasks = vec![task(vec!["a"]), task(vec!["b"])];
+    let settings = two_in_flight();
+    let until = GroupEnd::ProcessAll;
+    let ((execution, start_errors), mut queue) =
+        group::start_parallel_tasks(tasks, PARENT_DIR, &until, &settings);
+    assert!(start_errors.is_empty());
+    // Same sub-crate, but different target directories, so neither waits for the other.
+    assert_eq!(execution.0.len(), 2);
+    assert!(queue.is_empty());
+
+    let outputs = group::life_cycle_loop(execution, &mut queue, &until).unwrap();
+    assert_eq!(outputs.len(), 2);
+    for (output, error) in &outputs {
+        assert!(!output::has_error(output, error));
+        let (process_output, features, _, _) = output.as_ref().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&process_output.as_ref().unwrap().stdout),
+            format!("Features: [\"{features}\"]\n")
+        );
+    }
+    let target_dirs = fs::read_dir("testbins/fixture/target/feature-sets")
+        .unwrap()
+        .count();
+    assert!(target_dirs >= 2);
+}
+
+#[test]
+fn per_feature_set_target_dirs_depend_on_build_env() {
+    let target_dir = |features: Vec<&str>, build_env: &[(&str, &str)]| {
+        let settings = TaskSettings {
+            target_dir: TargetDir::PerFeatureSet,
+            build_env: build_env
+                .iter()
+                .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+                .collect(),
+            ..TaskSettings::default()
+        };
+        let manifest_path = task::manifest_path_for_subdir(PARENT_DIR, FIXTURE);
+        task::target_dir(&manifest_path, &features, &settings).unwrap()
+    };
+    let flags = ("RUSTFLAGS", "--cfg fixture_cfg");
+    let other = ("OTHER", "1");
+    assert_eq!(
+        target_dir(vec!["a", "b"], &[]),
+        target_dir(vec!["b", "a"], &[])
+    );
+    assert_ne!(target_dir(vec!["a"], &[]), target_dir(vec!["a"], &[flags]));
+    assert_eq!(
+        target_dir(vec!["a"], &[flags, other]),
+        target_dir(vec!["a"], &[other, flags])
+    );
+    assert_eq!(
+        target_dir(vec!["a"], &[("RUSTFLAGS", ""), flags]),
+        target_dir(vec!["a"], &[flags])
+    );
+}
+
+#[test]
+fn leases_are_per_effective_target_dir_and_binary_name() {
+    let effective_target_dir = |sub_dir: &str, target_dir: TargetDir, cargo_target_dir: &str| {
+        let settings = TaskSettings {
+            target_dir,
+            build_env: vec![("CARGO_TARGET_DIR".to_owned(), cargo_target_dir.to_owned())],
+            ..TaskSettings::default()
+        };
+        let manifest_path = task::manifest_path_for_subdir(PARENT_DIR, sub_dir);
+        let target_dir = task::target_dir(&manifest_path, &Vec::<&str>::new(), &settings);
+        task::effective_target_dir(&manifest_path, target_dir.as_deref(), &settings)
+    };
+    // Shared, but with `CARGO_TARGET_DIR` being the sub-crate's `target/`, spelled differently.
+    let fixture_target = std::env::current_dir()
+        .unwrap()
+        .join("testbins/fixture/target");
+    let fixture_target = fixture_target.to_str().unwrap();
+    assert_eq!(
+        effective_target_dir(FIXTURE, TargetDir::Shared, fixture_target),
+        effective_target_dir(FIXTURE, TargetDir::PerSubCrate, fixture_target)
+    );
+    // Different sub-crates, sharing `CARGO_TARGET_DIR`.
+    let shared = effective_target_dir(FIXTURE, TargetDir::Shared, "shared-target");
+    assert_eq!(
+        shared,
+        effective_target_dir("other", TargetDir::Shared, "shared-target")
+    );
+    assert_ne!(
+        shared,
+        effective_target_dir("other", TargetDir::PerSubCrate, "shared-target")
+    );
+
+    // A binary name that no other test builds.
+    let lease = ExecutableLease::try_new(shared.clone(), "leased".to_owned()).unwrap();
+    assert!(ExecutableLease::try_new(shared.clone(), "leased".to_owned()).is_none());
+    assert!(ExecutableLease::try_new(shared.clone(), "not-leased".to_owned()).is_some());
+    drop(lease);
+    assert!(ExecutableLease::try_new(shared, "leased".to_owned()).is_some());
+}
+
+#[test]
+fn builds_of_same_sub_crate_take_turns_but_run_in_parallel() {
+    let task = |features: Vec<&'static str>, timeout: Option<Duration>| {
+        let settings = TaskSettings {
+            timeout,
+            ..TaskSettings::default()
+        };
+        fixture_task(features, settings, ())
+    };
+    // The first one runs until it times out, so the second one has to run alongside it.
+    let tasks = vec![
+        task(vec!["sleep"], Some(Duration::from_secs(5))),
+        task(vec!["a"], None),
+    ];
+    let settings = two_in_flight();
+    let until = GroupEnd::ProcessAll;
+    let ((execution, start_errors), mut queue) =
+        group::start_parallel_tasks(tasks, PARENT_DIR, &until, &settings);
+    assert!(start_errors.is_empty());
+    assert_eq!(execution.0.len(), 1);
+    assert_eq!(queue.len(), 1);
+
+    let outputs = group::life_cycle_loop(execution, &mut queue, &until).unwrap();
+    let infos: Vec<&str> = outputs
+        .iter()
+        .map(|(output, _)| output.as_ref().unwrap().1.as_str())
+        .collect();
+    assert_eq!(infos, vec!["a", "sleep"]);
+    assert!(!output::has_error(&outputs[0].0, &outputs[0].1));
+    assert!(outputs[1]
+        .1
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<TimedOut>()
+        .is_some());
+}
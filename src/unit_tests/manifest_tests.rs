@@ -0,0 +1,208 @@
+use super::{fixture_task, FIXTURE, PARENT_DIR};
+use crate::{
+    group::{GroupSettings, TaskSettings},
+    indicators::{BinaryCrateName, GroupEnd},
+    manifest::{self, InvalidManifest, UnknownFeature, UnknownFeatures},
+    run,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Write a manifest with the given content into a new temporary directory. Return path of the
+/// manifest.
+fn temp_manifest(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let manifest_path = dir.join("Cargo.toml");
+    fs::write(&manifest_path, content).unwrap();
+    manifest_path
+}
+
+fn remove_temp_manifest(manifest_path: &Path) {
+    fs::remove_dir_all(manifest_path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn read_features_of_fixture() {
+    let table = manifest::read_features_for_subdir(PARENT_DIR, FIXTURE).unwrap();
+    assert_eq!(table["default"], Vec::<String>::new());
+    assert_eq!(
+        manifest::feature_names(&table),
+        vec!["a", "b", "compile_error", "fail", "sleep", "stderr"]
+    );
+}
+
+#[test]
+fn read_features_without_table() {
+    let table = manifest::read_features_for_subdir(PARENT_DIR, "other").unwrap();
+    assert!(table.is_empty());
+}
+
+#[test]
+fn read_features_rejects_invalid_table() {
+    let manifest_path = temp_manifest("invalid-features", "[features]\na = \"b\"\n");
+    let err = manifest::read_features(&manifest_path).unwrap_err();
+    remove_temp_manifest(&manifest_path);
+    assert!(err.downcast_ref::<InvalidManifest>().is_some());
+}
+
+#[test]
+fn resolve_features_transitively() {
+    let table = manifest::read_features_for_subdir(PARENT_DIR, FIXTURE).unwrap();
+    assert_eq!(
+        manifest::resolve_features(&table, &["a", "unknown"], false)
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec!["a", "default"]
+    );
+}
+
+#[test]
+fn read_features_of_optional_dependencies() {
+    let manifest_path = temp_manifest(
+        "optional-dependencies",
+        "[dependencies]\n\
+        implicit = { version = \"1\", optional = true }\n\
+        explicit = { version = \"1\", optional = true }\n\
+        required = \"1\"\n\
+        [target.'cfg(unix)'.build-dependencies]\n\
+        unix = { version = \"1\", optional = true }\n\
+        [features]\n\
+        uses-explicit = [\"dep:explicit\"]\n",
+    );
+    let table = manifest::read_features(&manifest_path).unwrap();
+    remove_temp_manifest(&manifest_path);
+    assert_eq!(
+        table.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("implicit".to_owned(), vec!["dep:implicit".to_owned()]),
+            ("unix".to_owned(), vec!["dep:unix".to_owned()]),
+            ("uses-explicit".to_owned(), vec!["dep:explicit".to_owned()]),
+        ]
+    );
+}
+
+#[test]
+fn unknown_features_are_rejected_before_building() {
+    let task = |features| fixture_task(features, TaskSettings::default(), ());
+    let tasks = vec![task(vec!["a", "aa"]), task(vec!["aa", "sleeep", "zzz"])];
+    let err = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap_err();
+    let unknown = |feature: &str, suggestions: &[&str]| UnknownFeature {
+        sub_dir: "fixture".to_owned(),
+        feature: feature.to_owned(),
+        suggestions: suggestions.iter().map(|s| (*s).to_owned()).collect(),
+    };
+    let err = err.downcast_ref::<UnknownFeatures>().unwrap();
+    assert_eq!(
+        *err,
+        UnknownFeatures(vec![
+            unknown("aa", &["a"]),
+            unknown("sleeep", &["sleep"]),
+            unknown("zzz", &[]),
+        ])
+    );
+    assert_eq!(
+        err.to_string(),
+        "Unknown features: aa (of fixture/) - did you mean a?, sleeep (of fixture/) - did you \
+        mean sleep?, zzz (of fixture/)."
+    );
+}
+
+#[test]
+fn resolve_dependency_and_weak_features() {
+    let manifest_path = temp_manifest(
+        "dependency-features",
+        "[dependencies]\n\
+        serde = { version = \"1\", optional = true }\n\
+        log = { version = \"0.4\", optional = true }\n\
+        [features]\n\
+        derive = [\"dep:serde\", \"serde/derive\"]\n\
+        std = [\"serde?/std\", \"log?/std\"]\n\
+        verbose = [\"log/max_level_trace\"]\n",
+    );
+    let table = manifest::read_features(&manifest_path).unwrap();
+    remove_temp_manifest(&manifest_path);
+    // `serde` is hidden by `dep:`, so it has no implicit feature.
+    assert_eq!(
+        manifest::feature_names(&table),
+        vec!["derive", "log", "std", "verbose"]
+    );
+
+    let resolve = |features: &[&str]| {
+        manifest::resolve_features(&table, features, false)
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(resolve(&["std"]), vec!["std"]);
+    assert_eq!(
+        resolve(&["std", "derive"]),
+        vec!["dep:serde", "derive", "serde/derive", "serde/std", "std"]
+    );
+    assert_eq!(
+        resolve(&["verbose"]),
+        vec!["dep:log", "log", "log/max_level_trace", "verbose"]
+    );
+    assert_eq!(resolve(&["verbose"]), resolve(&["log", "verbose"]));
+    assert_eq!(
+        resolve(&["log", "std"]),
+        vec!["dep:log", "log", "log/std", "std"]
+    );
+}
+
+#[test]
+fn features_of_dependencies_are_known() {
+    let manifest_path = temp_manifest(
+        "features-of-dependencies",
+        "[dependencies]\n\
+        serde = \"1\"\n\
+        log = { version = \"0.4\", optional = true }\n\
+        [target.'cfg(unix)'.build-dependencies]\n\
+        cc = \"1\"\n\
+        [features]\n\
+        std = []\n",
+    );
+    let dir = manifest_path.parent().unwrap();
+    let parent_dir = dir.parent().unwrap().to_str().unwrap();
+    let sub_dir = dir.file_name().unwrap().to_str().unwrap();
+    let binary_crate = BinaryCrateName::Main;
+    let tasks = vec![(
+        sub_dir,
+        &binary_crate,
+        vec![
+            "std",
+            "serde/derive",
+            "serde?/std",
+            "log/std",
+            "log?/serde",
+            "cc/parallel",
+            "rand/std",
+            "rand?/std",
+        ],
+        TaskSettings::default(),
+        String::new(),
+        (),
+    )];
+    let result = manifest::validate_tasks(parent_dir, &tasks);
+    remove_temp_manifest(&manifest_path);
+    let err = result.unwrap_err();
+    let err = err.downcast_ref::<UnknownFeatures>().unwrap();
+    assert_eq!(
+        *err,
+        UnknownFeatures(
+            ["rand/std", "rand?/std"]
+                .iter()
+                .map(|feature| UnknownFeature {
+                    sub_dir: sub_dir.to_owned(),
+                    feature: (*feature).to_owned(),
+                    suggestions: Vec::new(),
+                })
+                .collect()
+        )
+    );
+}
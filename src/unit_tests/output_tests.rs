@@ -33,14 +33,14 @@ fn has_error() {
     let ok = output_ok();
     let ok_status = ok.status;
     assert!(!output::has_error(
-        &Some((Some(ok), "ok".to_owned(), "meta")),
+        &Some((Some(ok), "ok".to_owned(), "meta", None)),
         &None
     ));
 
     let failed = output_failed();
     let failed_status = failed.status;
     assert!(output::has_error(
-        &Some((Some(failed), "failed".to_owned(), 12 /*meta*/)),
+        &Some((Some(failed), "failed".to_owned(), 12 /*meta*/, None)),
         &None
     ));
 
@@ -52,6 +52,7 @@ fn has_error() {
         }),
         "ok_outputs_but_failed_status".to_owned(),
         (), /*meta*/
+        None,
     ));
     assert!(output::has_error(&ok_outputs_but_failed_status, &None));
 
@@ -63,6 +64,7 @@ fn has_error() {
         }),
         "failed_outputs_but_ok_status".to_owned(),
         "meta",
+        None,
     ));
     assert!(output::has_error(&failed_outputs_but_ok_status, &None));
 
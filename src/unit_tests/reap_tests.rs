@@ -0,0 +1,45 @@
+use crate::group::{ChildInfoMeta, GroupOfChildren};
+use crate::reap;
+use core::time::Duration;
+use std::process::Command;
+use std::time::Instant;
+
+fn children(script: &str) -> GroupOfChildren<()> {
+    let child = Command::new("/usr/bin/sh")
+        .arg("-c")
+        .arg(script)
+        .spawn()
+        .unwrap();
+    let mut children = GroupOfChildren::new();
+    children.insert(
+        child.id().into(),
+        ChildInfoMeta::new(child, script.to_owned(), ()),
+    );
+    children
+}
+
+#[test]
+fn wait_for_any_child_times_out() {
+    let children = children("exec sleep 30");
+    let start = Instant::now();
+    reap::wait_for_any_child(&children, Some(Duration::from_millis(100))).unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(100) || cfg!(not(target_os = "linux")));
+    assert!(elapsed < Duration::from_secs(10));
+
+    for (_, mut child_info_meta) in children {
+        let _ = child_info_meta.child_mut().kill();
+        let _ = child_info_meta.child_mut().wait();
+    }
+}
+
+#[test]
+fn wait_for_any_child_wakes_up_on_exit() {
+    let mut children = children("sleep 0.1");
+    let start = Instant::now();
+    reap::wait_for_any_child(&children, Some(Duration::from_secs(30))).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    let (_, child_info_meta) = children.iter_mut().next().unwrap();
+    assert!(child_info_meta.child_mut().try_wait().unwrap().is_some());
+}
@@ -0,0 +1,412 @@
+use super::{fixture_task, strings, FIXTURE, FIXTURE_CRATE, PARENT_DIR};
+use crate::{
+    combinations,
+    constraints::Constraints,
+    group::{GroupSettings, ParallelTasks, Stopped, TaskSettings, TimedOut},
+    indicators::{BinaryCrateName, GroupEnd, SequenceEnd},
+    output, run,
+    task::{
+        BuildFailed, BuildFailedDifferently, ExpectedBuildFailure, TargetDir,
+        UnexpectedBuildSuccess,
+    },
+};
+use std::time::{Duration, Instant};
+
+fn task(features: Vec<&'static str>) -> ParallelTasks<'static, str, &'static str> {
+    vec![fixture_task(features, TaskSettings::default(), "meta")]
+}
+
+#[test]
+fn parallel_single_tasks_runs_all() {
+    let tasks = task(vec!["a"])
+        .into_iter()
+        .chain(task(vec!["fail"]))
+        .collect();
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    assert_eq!(outputs.len(), 2);
+    let failed = outputs
+        .iter()
+        .filter(|(output, error)| output::has_error(output, error))
+        .count();
+    assert_eq!(failed, 1);
+}
+
+#[test]
+fn build_output_is_separate_from_run_output() {
+    let tasks = task(vec!["a"])
+        .into_iter()
+        .chain(task(vec!["compile_error"]))
+        .collect();
+    let mut outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    outputs.sort_by_key(|(output, _)| output.as_ref().unwrap().1.clone());
+
+    let ((built, _), (failed, error)) = (&outputs[0], &outputs[1]);
+    let (process_output, info, _, build_output) = built.as_ref().unwrap();
+    assert_eq!(info, "a");
+    assert_eq!(
+        process_output.as_ref().unwrap().stdout,
+        b"Features: [\"a\"]\n"
+    );
+    assert!(build_output.as_ref().unwrap().0.status.success());
+
+    let (process_output, info, _, build_output) = failed.as_ref().unwrap();
+    assert_eq!(info, "compile_error");
+    assert!(process_output.is_none());
+    assert!(error
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<BuildFailed>()
+        .is_some());
+    let (build_output, _) = build_output.as_ref().unwrap();
+    assert!(
+        String::from_utf8_lossy(&build_output.stderr).contains("Failing to build, as requested.")
+    );
+}
+
+#[test]
+fn main_binary_crate_is_named_after_package() {
+    let tasks = vec![(
+        FIXTURE,
+        &BinaryCrateName::Main,
+        vec!["a"],
+        TaskSettings::default(),
+        "main".to_owned(),
+        "meta",
+    )];
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    let (output, error) = &outputs[0];
+    assert!(!output::has_error(output, error));
+    assert_eq!(
+        output.as_ref().unwrap().0.as_ref().unwrap().stdout,
+        b"Features: [\"a\"]\n"
+    );
+}
+
+#[test]
+fn expected_build_failure_counts_as_success() {
+    let mut tasks: ParallelTasks<'static, str, &'static str> = task(vec!["a"])
+        .into_iter()
+        .chain(task(vec!["compile_error"]))
+        .collect();
+    Constraints {
+        forbidden: vec![vec!["compile_error".to_owned()]],
+        ..Constraints::default()
+    }
+    .expect_build_failures(&mut tasks, None);
+    assert!(tasks[0].3.expected_build_failure.is_none());
+    assert!(tasks[1].3.expected_build_failure.is_some());
+
+    let expect = |message: &str| ExpectedBuildFailure {
+        message: Some(message.to_owned()),
+    };
+    let mut unexpected = task(vec!["a"]);
+    unexpected[0].4 = "unexpected".to_owned();
+    unexpected[0].3.expected_build_failure = Some(ExpectedBuildFailure::default());
+    let mut different = task(vec!["compile_error"]);
+    different[0].4 = "different".to_owned();
+    different[0].3.expected_build_failure = Some(expect("Some other message."));
+    let mut matching = task(vec!["compile_error"]);
+    matching[0].4 = "matching".to_owned();
+    matching[0].3.expected_build_failure = Some(expect("Failing to build, as requested."));
+    tasks.extend(unexpected.into_iter().chain(different).chain(matching));
+
+    let mut outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    outputs.sort_by_key(|(output, _)| output.as_ref().unwrap().1.clone());
+    let infos: Vec<&str> = outputs
+        .iter()
+        .map(|(output, _)| output.as_ref().unwrap().1.as_str())
+        .collect();
+    assert_eq!(
+        infos,
+        vec!["a", "compile_error", "different", "matching", "unexpected"]
+    );
+
+    for (output, error) in &outputs[..2] {
+        assert!(!output::has_error(output, error));
+    }
+    assert!(outputs[1].0.as_ref().unwrap().0.is_none());
+    assert!(outputs[2]
+        .1
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<BuildFailedDifferently>()
+        .is_some());
+    assert!(outputs[3].1.is_none());
+    let (output, error) = &outputs[4];
+    assert!(output.as_ref().unwrap().0.is_none());
+    assert!(error
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<UnexpectedBuildSuccess>()
+        .is_some());
+}
+
+#[test]
+fn build_and_run_environment() {
+    let env = |key: &str, value: &str| vec![(key.to_owned(), value.to_owned())];
+    let mut hermetic = task(vec!["a", "b"]);
+    hermetic[0].3 = TaskSettings {
+        // Not to rebuild the shared target directory (of other tests) with different RUSTFLAGS.
+        target_dir: TargetDir::PerFeatureSet,
+        build_env: env("RUSTFLAGS", "--cfg fixture_cfg"),
+        run_env: env("FIXTURE_ECHO", "hermetic"),
+        clear_run_env: true,
+        ..TaskSettings::default()
+    };
+    let mut inherited = task(vec!["a"]);
+    inherited[0].3.run_env = env("FIXTURE_ECHO", "inherited");
+
+    let mut outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        hermetic.into_iter().chain(inherited).collect(),
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+    outputs.sort_by_key(|(output, _)| output.as_ref().unwrap().1.clone());
+    let stdouts: Vec<String> = outputs
+        .iter()
+        .map(|(output, error)| {
+            assert!(!output::has_error(output, error));
+            let process_output = output.as_ref().unwrap().0.as_ref().unwrap();
+            String::from_utf8_lossy(&process_output.stdout).into_owned()
+        })
+        .collect();
+    assert_eq!(
+        stdouts[0],
+        format!(
+            "Features: [\"a\"]\nEcho: inherited (of {} environment variables).\n",
+            std::env::vars_os().count() + 1
+        )
+    );
+    assert_eq!(
+        stdouts[1],
+        "Features: [\"a\", \"b\"]\nBuilt with --cfg fixture_cfg.\nEcho: hermetic (of 1 environment \
+        variables).\n"
+    );
+}
+
+#[test]
+fn parallel_tasks_for_args_with_working_dir() {
+    let arg_sets = vec![strings(&["x"]), strings(&["y", "z"])];
+    let tasks = combinations::parallel_tasks_for_args(
+        FIXTURE,
+        &FIXTURE_CRATE,
+        vec![vec!["a"]],
+        &arg_sets,
+        None,
+        &TaskSettings::default(),
+    )
+    .into_iter()
+    .chain(combinations::parallel_tasks(
+        FIXTURE,
+        &FIXTURE_CRATE,
+        vec![vec![]],
+        None,
+        &TaskSettings {
+            args: strings(&["in-src"]),
+            working_dir: Some("src".into()),
+            no_default_features: true,
+            ..TaskSettings::default()
+        },
+    ))
+    .collect();
+    let outputs = run::parallel_single_tasks(
+        PARENT_DIR,
+        tasks,
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let fixture_dir = std::env::current_dir().unwrap().join("testbins/fixture");
+    let mut stdouts: Vec<(String, String)> = outputs
+        .iter()
+        .map(|(output, error)| {
+            assert!(!output::has_error(output, error));
+            let (process_output, info, _, _) = output.as_ref().unwrap();
+            (
+                info.clone(),
+                String::from_utf8_lossy(&process_output.as_ref().unwrap().stdout)
+                    .replace(&fixture_dir.display().to_string(), "<fixture>"),
+            )
+        })
+        .collect();
+    stdouts.sort();
+    let info = |features: &str, rest: &str| {
+        format!("fixture/ binary crate fixture with features [{features}]{rest}")
+    };
+    assert_eq!(
+        stdouts,
+        vec![
+            (
+                info("", ", no default features, args [\"in-src\"]"),
+                "Features: []\nArgs: [\"in-src\"]\nWorking directory: <fixture>/src\n".to_owned()
+            ),
+            (
+                info("", ", no default features, args [\"x\"]"),
+                "Features: []\nArgs: [\"x\"]\nWorking directory: <fixture>\n".to_owned()
+            ),
+            (
+                info("", ", no default features, args [\"y\", \"z\"]"),
+                "Features: []\nArgs: [\"y\", \"z\"]\nWorking directory: <fixture>\n".to_owned()
+            ),
+            (
+                info("a", ", args [\"x\"]"),
+                "Features: [\"a\"]\nArgs: [\"x\"]\nWorking directory: <fixture>\n".to_owned()
+            ),
+            (
+                info("a", ", args [\"y\", \"z\"]"),
+                "Features: [\"a\"]\nArgs: [\"y\", \"z\"]\nWorking directory: <fixture>\n"
+                    .to_owned()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn sequence_stops_on_others_failure() {
+    // The other sequence fails only once its task times out, by when this sequence's first task
+    // runs for sure.
+    let mut timing_out = task(vec!["sleep"]);
+    timing_out[0].3.timeout = Some(Duration::from_secs(10));
+    let settings = GroupSettings {
+        // Not to wait for jobserver tokens taken by any other tests.
+        use_jobserver: false,
+        ..GroupSettings::default()
+    };
+    let start = Instant::now();
+    let results = run::parallel_sequences_of_parallel_tasks(
+        PARENT_DIR,
+        vec![
+            (
+                GroupEnd::ProcessAll,
+                SequenceEnd::ContinueRegardlessOfOthers,
+                vec![timing_out],
+            ),
+            (
+                GroupEnd::OnFailureStopAll,
+                SequenceEnd::StopOnOthersFailure,
+                vec![task(vec!["sleep"]), task(vec!["a"])],
+            ),
+        ],
+        &settings,
+    )
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(25));
+
+    let (failing_steps, failing_skipped) = &results[0];
+    assert_eq!(failing_steps.len(), 1);
+    assert!(failing_steps[0][0]
+        .1
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<TimedOut>()
+        .is_some());
+    assert!(failing_skipped.is_empty());
+
+    let (stopped_steps, stopped_skipped) = &results[1];
+    assert_eq!(stopped_steps.len(), 1);
+    assert_eq!(stopped_steps[0].len(), 1);
+    assert!(stopped_steps[0][0]
+        .1
+        .as_ref()
+        .unwrap()
+        .downcast_ref::<Stopped>()
+        .is_some());
+    assert_eq!(stopped_skipped.len(), 1);
+    assert_eq!(stopped_skipped[0][0].2, vec!["a"]);
+}
+
+#[test]
+fn sequence_does_not_stop_on_its_own_failure() {
+    let results = run::parallel_sequences_of_parallel_tasks(
+        PARENT_DIR,
+        vec![(
+            GroupEnd::ProcessAll,
+            SequenceEnd::StopOnOthersFailure,
+            vec![task(vec!["fail"]), task(vec!["a"])],
+        )],
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let (steps, skipped) = &results[0];
+    assert!(skipped.is_empty());
+    assert_eq!(steps.len(), 2);
+    assert!(output::has_error(&steps[0][0].0, &steps[0][0].1));
+    assert!(!output::has_error(&steps[1][0].0, &steps[1][0].1));
+}
+
+#[test]
+fn sequence_continues_regardless_of_others() {
+    let results = run::parallel_sequences_of_parallel_tasks(
+        PARENT_DIR,
+        vec![
+            (
+                GroupEnd::ProcessAll,
+                SequenceEnd::ContinueRegardlessOfOthers,
+                vec![task(vec!["fail"])],
+            ),
+            (
+                GroupEnd::OnFailureStopAll,
+                SequenceEnd::ContinueRegardlessOfOthers,
+                vec![task(vec!["a"]), task(vec!["b"])],
+            ),
+        ],
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let (steps, skipped) = &results[1];
+    assert_eq!(steps.len(), 2);
+    assert!(skipped.is_empty());
+    assert!(steps
+        .iter()
+        .flatten()
+        .all(|(output, error)| !output::has_error(output, error)));
+}
+
+#[test]
+fn sequence_under_process_all_continues_after_failure() {
+    let results = run::parallel_sequences_of_parallel_tasks(
+        PARENT_DIR,
+        vec![(
+            GroupEnd::ProcessAll,
+            SequenceEnd::ContinueRegardlessOfOthers,
+            vec![task(vec!["fail"]), task(vec!["a"])],
+        )],
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let (steps, skipped) = &results[0];
+    assert!(skipped.is_empty());
+    assert_eq!(steps.len(), 2);
+    assert!(output::has_error(&steps[0][0].0, &steps[0][0].1));
+    assert!(!output::has_error(&steps[1][0].0, &steps[1][0].1));
+}
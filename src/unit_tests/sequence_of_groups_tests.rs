@@ -0,0 +1,89 @@
+use crate::{
+    group::{GroupSettings, TaskSettings},
+    indicators::{BinaryCrateName, GroupEnd},
+    sequence_of_groups::{self, StepResult},
+};
+
+use super::{FIXTURE, PARENT_DIR};
+
+fn features_of<'s>(results: &[StepResult<'s, str>]) -> Vec<Vec<&'s str>> {
+    results
+        .iter()
+        .map(|result| result.features().clone())
+        .collect()
+}
+
+#[test]
+fn sequence_single_tasks_reorders_stderr_last() {
+    let results = sequence_of_groups::sequence_single_tasks(
+        PARENT_DIR,
+        FIXTURE,
+        &BinaryCrateName::Other(FIXTURE),
+        [vec!["a"], vec!["stderr"], vec!["b"]],
+        &TaskSettings::default(),
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        features_of(&results),
+        vec![vec!["a"], vec!["b"], vec!["stderr"]]
+    );
+    assert!(results
+        .iter()
+        .all(|result| matches!(result, StepResult::Run(..))));
+    assert!(results[2].has_stderr());
+}
+
+#[test]
+fn sequence_single_tasks_skips_after_failure() {
+    let results = sequence_of_groups::sequence_single_tasks(
+        PARENT_DIR,
+        FIXTURE,
+        &BinaryCrateName::Other(FIXTURE),
+        [vec!["fail"], vec!["a"]],
+        &TaskSettings::default(),
+        GroupEnd::OnFailureFinishActive,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    match &results[..] {
+        [StepResult::Run(failed, (Some((Some(output), _, _, _)), None)), StepResult::Skipped(skipped)] =>
+        {
+            assert_eq!(failed, &vec!["fail"]);
+            assert!(!output.status.success());
+            assert_eq!(skipped, &vec!["a"]);
+        }
+        _ => panic!("Unexpected results."),
+    }
+}
+
+#[test]
+fn sequence_single_tasks_with_task_settings() {
+    let results = sequence_of_groups::sequence_single_tasks(
+        PARENT_DIR,
+        "defaults",
+        &BinaryCrateName::Other("defaults"),
+        [vec![], vec!["off"]],
+        &TaskSettings {
+            no_default_features: true,
+            ..TaskSettings::default()
+        },
+        GroupEnd::ProcessAll,
+        &GroupSettings::default(),
+    )
+    .unwrap();
+
+    let stdouts: Vec<String> = results
+        .iter()
+        .map(|result| match result {
+            StepResult::Run(_, (Some((Some(output), _, _, _)), None)) => {
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            _ => panic!("Unexpected result."),
+        })
+        .collect();
+    assert_eq!(stdouts, vec!["Features: []\n", "Features: [\"off\"]\n"]);
+}
@@ -0,0 +1,13 @@
+//! A binary crate with default features, for testing `test-binary-features` itself with and
+//! without default features.
+
+fn main() {
+    let features: Vec<&str> = [
+        #[cfg(feature = "on")]
+        "on",
+        #[cfg(feature = "off")]
+        "off",
+    ]
+    .to_vec();
+    println!("Features: {features:?}");
+}
@@ -0,0 +1,49 @@
+//! A binary crate for testing `test-binary-features` itself. Its behavior depends on its features.
+
+#[cfg(feature = "compile_error")]
+compile_error!("Failing to build, as requested.");
+
+fn main() {
+    let features: Vec<&str> = [
+        #[cfg(feature = "a")]
+        "a",
+        #[cfg(feature = "b")]
+        "b",
+        #[cfg(feature = "fail")]
+        "fail",
+        #[cfg(feature = "stderr")]
+        "stderr",
+        #[cfg(feature = "sleep")]
+        "sleep",
+    ]
+    .to_vec();
+    println!("Features: {features:?}");
+    if cfg!(fixture_cfg) {
+        println!("Built with --cfg fixture_cfg.");
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        println!("Args: {args:?}");
+        println!(
+            "Working directory: {}",
+            std::env::current_dir().unwrap().display()
+        );
+    }
+    if let Some(echo) = std::env::var_os("FIXTURE_ECHO") {
+        println!(
+            "Echo: {} (of {} environment variables).",
+            echo.to_string_lossy(),
+            std::env::vars_os().count()
+        );
+    }
+
+    if cfg!(feature = "stderr") {
+        eprintln!("Writing to stderr, as requested.");
+    }
+    if cfg!(feature = "sleep") {
+        std::thread::sleep(std::time::Duration::from_secs(30));
+    }
+    if cfg!(feature = "fail") {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,6 @@
+//! Another binary crate for testing `test-binary-features` itself, so that tests can have tasks
+//! under different sub-crates.
+
+fn main() {
+    println!("Other.");
+}